@@ -0,0 +1,61 @@
+//! Full-machine sized benchmark of the propagation engines; run with `cargo bench`
+
+use std::time::Instant;
+
+use z3mu::handle;
+use z3mu::circuit::{Circuit, CircuitBuilder, Engine, Handle, NodeId};
+
+const STEPS: u32 = 200;
+
+/// Linear congruential generator so that the netlist and inputs are reproducible
+struct Lcg(u64);
+
+impl Lcg {
+    fn below(&mut self, bound: usize) -> usize {
+        self.0 = self.0.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+        (self.0 >> 33) as usize % bound
+    }
+}
+
+/// Builds a netlist of randomly wired relays with input labels `in_0` to `in_15`, about the size
+/// of the Z3
+fn random_netlist(seed: u64, relays: usize, contacts_per_relay: usize) -> Circuit {
+    let mut rng = Lcg(seed);
+    CircuitBuilder::new().add_subcircuit(|mut scb| {
+        let g = scb.label("G");
+        let mut nodes: Vec<NodeId> = (0..16).map(|i| scb.label(handle!("in", i))).collect();
+        nodes.push(g);
+        nodes.extend((0..relays * contacts_per_relay).map(|_| scb.node(None)));
+        for relay in 0..relays {
+            let (name, index) = (relay / 100, (relay % 100) as i8);
+            let coil_pos = nodes[rng.below(nodes.len())];
+            scb.coil(Handle::new(format!("R{}", name), Some(index), None), coil_pos);
+            for _ in 0..contacts_per_relay {
+                let pole = nodes[rng.below(nodes.len())];
+                let no = (rng.below(2) == 0).then(|| nodes[rng.below(nodes.len())]);
+                let nc = (rng.below(2) == 0).then(|| nodes[rng.below(nodes.len())]);
+                scb.add_switch(Handle::new(format!("r{}", name), Some(index), None), (pole, no, nc));
+            }
+        }
+    }).finalize()
+}
+
+fn main() {
+    let mut rng = Lcg(0);
+    let schedule: Vec<Vec<Handle>> = (0..STEPS)
+        .map(|_| (0..rng.below(4)).map(|_| handle!("in", rng.below(16) as i8)).collect())
+        .collect();
+
+    for engine in [Engine::TwoPhase, Engine::EventDriven] {
+        let mut c = random_netlist(1941, 2000, 15);
+        c.set_engine(engine);
+        let start = Instant::now();
+        for inputs in &schedule {
+            for input in inputs {
+                c.set(input);
+            }
+            c.step();
+        }
+        println!("{:?}: {:?}/step", engine, start.elapsed() / STEPS);
+    }
+}
@@ -1,33 +1,96 @@
 use std::collections::HashMap;
 use log::*;
 
+pub use batch::BatchCircuit;
+pub use clock::PhaseClock;
+pub use cycles::LimitCycle;
+pub use directory::{NodeInfo, Terminal};
+pub use electrical::{Rail, ShortCircuit};
+pub use engine::Engine;
+pub use error::{FileError, Z3muError};
+pub use explain::{BlockedPath, ConductionPath, Link};
 pub use handle::{Bus, Handle};
+pub use history::OutOfHistory;
+pub use netlist::NetlistError;
+pub use observer::{ObserverId, SimObserver};
+pub use ports::{Binding, Direction, Port, Subcircuit};
+pub use relays::RelayContact;
+pub use session::{ReplayError, Session, SessionEntry};
+pub use settle::Unstable;
+pub use snapshot::{Snapshot, SnapshotError};
 pub use subcircuit::{SubcircuitBuilder, CircuitBuilder};
+pub use timing::Timing;
+pub use validate::{Issue, Severity, ValidationReport};
+pub use vcd::VcdWriter;
+pub use topology::ContactKind;
+use topology::{BitSet, Contact, Csr};
 
 #[macro_use]
 pub mod handle;
+pub mod batch;
+pub mod clock;
+pub mod codegen;
+pub mod cycles;
+pub mod directory;
+pub mod electrical;
+pub mod engine;
+pub mod error;
+pub mod explain;
+pub mod history;
+pub mod netlist;
+pub mod observer;
+pub mod ports;
+pub mod relays;
+pub mod scope;
+pub mod session;
+pub mod settle;
+pub mod snapshot;
 pub mod subcircuit;
+pub mod timing;
+pub mod topology;
+pub mod traces;
+pub mod validate;
+pub mod vcd;
 
 pub struct Circuit {
     // construction
     num_nodes: usize,
-    coils: Vec<Vec<Coil>>, // NodeId -> Coils
+    coils: Csr<SwitchId>, // NodeId -> SwitchIds driven by coils on the node
+    contacts: Csr<Contact>, // NodeId -> Contacts touching the node
     switches: Vec<Switch>, // SwitchId -> Switch
     labels: HashMap<Handle, NodeId>,
+    coil_nodes: Vec<(Handle, NodeId)>, // coil handle -> node it sits on, sorted by name
+    directory: directory::NodeDirectory,
     traces: HashMap<NodeId, bool>,
     sources: Vec<NodeId>,
+    held: HashMap<Handle, NodeId>, // inputs pulled high on every tick until released
+    held_changed: bool, // held inputs changed since the last tick
+    step_sources: Vec<NodeId>, // sources replayed on every tick of the current step
+    ticks_per_step: u32,
     
     // state
-    switch_positions: Vec<bool>, // SwitchId -> bool
-    connections: Vec<Vec<NodeId>>, // NodeId -> NodeIds
+    switch_positions: BitSet, // SwitchId -> bool
+    armatures: timing::Armatures,
+    powered: Vec<bool>, // NodeId -> powered during the last step
+    last_positions: BitSet, // SwitchId -> position during the last tick's propagation
+    last_sources: Vec<NodeId>, // sources of the last tick, besides G
+    ticks: u64, // ticks elapsed since finalize
+    steps: u64, // steps taken since finalize
     initialized: bool,
+    engine: Engine,
+    two_phase: engine::TwoPhaseState,
+    event: engine::EventState,
+    cycles: Option<cycles::CycleDetector>,
+    clock: Option<PhaseClock>,
+    electrical: Option<electrical::Electrical>,
+    history: Option<history::History>,
+    recording: Option<Session>,
+    observers: observer::Observers,
 }
 
-struct Coil {
-    switches: Vec<SwitchId>,
-}
-
+#[derive(Clone)]
 struct Switch {
+    name: Handle, // handle of the relay operating the switch
     pole: NodeId,
     no: NodeId,
     nc: NodeId,
@@ -40,59 +103,157 @@ type SwitchId = usize;
 impl Circuit {
 
     pub fn set(&mut self, handle: &Handle) {
-        self.sources.push(self.labels[handle]);
+        self.try_set(handle).unwrap_or_else(|err| panic!("{}", err));
+    }
+
+    /// Like `set`, but reports a missing label instead of panicking
+    pub fn try_set(&mut self, handle: &Handle) -> Result<(), Z3muError> {
+        let node_id = self.node_of(handle).ok_or_else(|| unknown_label(handle))?;
+        self.sources.push(node_id);
+        self.record_entry(|_| SessionEntry::Set(handle.clone()));
+        Ok(())
     }
 
     pub fn set_bus(&mut self, bus: &Bus, k: i32) {
-        let mut max_index = 0i8;
-        let mut min_index = 31i8;
-        for (handle, node_id) in &self.labels {
-            if handle.name == bus.name && handle.sup == bus.sup {
-                let index = handle.index.expect("inspect_all called on a node with no index");
-                if (k >> index) & 1 != 0 {
-                    self.sources.push(*node_id);
-                }
-                max_index = std::cmp::max(max_index, index);
-                min_index = std::cmp::min(min_index, index);
+        self.try_set_bus(bus, k).unwrap_or_else(|err| panic!("{}", err));
+    }
+
+    /// Like `set_bus`, but reports a bus with no labelled members instead of panicking
+    pub fn try_set_bus(&mut self, bus: &Bus, k: i32) -> Result<(), Z3muError> {
+        let members = bus_members(&self.labels, bus);
+        if members.is_empty() {
+            return Err(Z3muError::UnknownBus { bus: bus.clone(), subcircuit: scope_of(&bus.name) });
+        }
+        for (index, node_id) in members {
+            if (k >> index) & 1 != 0 {
+                self.sources.push(node_id);
             }
         }
+        self.record_entry(|_| SessionEntry::SetBus(bus.clone(), k));
+        Ok(())
     }
 
-    pub fn inspect(&self, handle: &Handle) -> bool {
-        if let Some(node_id) = self.labels.get(handle) {
-            info!("{}: {}", handle, if self.traces[node_id] { 1 } else { 0 });
-            self.traces[node_id]
-        } else {
-            error!("Could not find node \"{}\" to inspect", handle);
-            panic!();
+    /// Pull a node high on every step until it is released
+    pub fn hold(&mut self, handle: &Handle) {
+        self.try_hold(handle).unwrap_or_else(|err| panic!("{}", err));
+    }
+
+    /// Like `hold`, but reports a missing label instead of panicking
+    pub fn try_hold(&mut self, handle: &Handle) -> Result<(), Z3muError> {
+        let node_id = self.node_of(handle).ok_or_else(|| unknown_label(handle))?;
+        self.held_changed |= self.held.insert(handle.clone(), node_id).is_none();
+        self.record_entry(|_| SessionEntry::Hold(handle.clone()));
+        Ok(())
+    }
+
+    pub fn release(&mut self, handle: &Handle) {
+        self.held_changed |= self.held.remove(handle).is_some();
+        self.record_entry(|_| SessionEntry::Release(handle.clone()));
+    }
+
+    /// Hold the members of a bus whose bits are set in `k` and release the others
+    pub fn hold_bus(&mut self, bus: &Bus, k: i32) {
+        for index in bus_members(&self.labels, bus).into_iter().map(|(index, _)| index) {
+            if (k >> index) & 1 != 0 {
+                self.hold(&bus.index(index));
+            } else {
+                self.release(&bus.index(index));
+            }
         }
     }
 
-    pub fn inspect_bus(&self, bus: &Bus) -> i32 {
-        let mut ret = 0i32;
-        let mut states = Vec::<(i8, bool)>::new();
-        for (handle, node_id) in &self.labels {
-            if handle.name == bus.name && handle.sup == bus.sup {
-                states.push((handle.index.expect("inspect_all called on a node with no index"), self.traces[node_id]));
-                if self.traces[node_id] {
-                    ret |= 1 << handle.index.unwrap();
-                }
+    pub fn release_bus(&mut self, bus: &Bus) {
+        for (index, _) in bus_members(&self.labels, bus) {
+            self.release(&bus.index(index));
+        }
+    }
+
+    /// Currently held inputs, sorted by name
+    pub fn held(&self) -> Vec<Handle> {
+        let mut held: Vec<Handle> = self.held.keys().cloned().collect();
+        held.sort_by_key(|handle| handle.to_string());
+        held
+    }
+
+    /// Whether a label was powered during the last tick, traced or not
+    pub fn inspect(&self, handle: &Handle) -> bool {
+        match self.try_inspect(handle) {
+            Ok(powered) => {
+                info!("{}: {}", handle, if powered { 1 } else { 0 });
+                powered
+            }
+            Err(err) => {
+                error!("{} to inspect", err);
+                panic!("{}", err);
             }
         }
-        assert!(!states.is_empty());
-        states.sort_by_key(|s| -s.0);
-        let max_index = states[0].0;
-        let min_index = states[states.len() - 1].0;
-        ret = (ret << (32 - max_index - 1)) >> (32 - max_index - 1);
+    }
+
+    /// Like `inspect`, but reports a missing label instead of panicking
+    pub fn try_inspect(&self, handle: &Handle) -> Result<bool, Z3muError> {
+        self.node_of(handle)
+            .map(|node_id| self.powered[node_id])
+            .ok_or_else(|| unknown_label(handle))
+    }
+
+    /// Traced nodes by their first label, with their state after the last tick, sorted by name
+    pub fn traced(&self) -> Vec<(String, bool)> {
+        let mut traced: Vec<(String, bool)> = self.traces
+            .iter()
+            .map(|(&node_id, &powered)| (self.node_name(node_id), powered))
+            .collect();
+        traced.sort();
+        traced
+    }
+
+    pub fn inspect_bus(&self, bus: &Bus) -> i32 {
+        let states: Vec<(i8, bool)> = bus_members(&self.labels, bus)
+            .into_iter()
+            .map(|(index, node_id)| (index, self.powered[node_id]))
+            .collect();
+        let ret = decode_bus(&states);
         info!("{}[{}:{}]: {}",
               bus,
-              max_index,
-              min_index,
+              states[0].0,
+              states[states.len() - 1].0,
               states.iter().map(|(_, state)| if *state { '1' } else { '0' }).collect::<String>());
         ret
     }
 }
 
+fn unknown_label(handle: &Handle) -> Z3muError {
+    Z3muError::UnknownLabel { handle: handle.clone(), subcircuit: scope_of(&handle.name) }
+}
+
+/// Scope named by the path of a name made in a scoped subcircuit, e.g. `fig7` for `fig7/+1`
+fn scope_of(name: &str) -> Option<String> {
+    name.rsplit_once('/').map(|(path, _)| path.to_string())
+}
+
+/// Indices and nodes of the labels making up a bus, highest index first
+fn bus_members(labels: &HashMap<Handle, NodeId>, bus: &Bus) -> Vec<(i8, NodeId)> {
+    let mut members: Vec<(i8, NodeId)> = labels
+        .iter()
+        .filter(|(handle, _)| handle.name == bus.name && handle.sup == bus.sup)
+        .map(|(handle, node_id)| (handle.index.expect("bus member with no index"), *node_id))
+        .collect();
+    members.sort_by_key(|(index, _)| -index);
+    members
+}
+
+/// Two's complement value of bus bit states ordered highest index first
+fn decode_bus(states: &[(i8, bool)]) -> i32 {
+    assert!(!states.is_empty());
+    let mut ret = 0i32;
+    for (index, state) in states {
+        if *state {
+            ret |= 1 << index;
+        }
+    }
+    let max_index = states[0].0;
+    (ret << (32 - max_index - 1)) >> (32 - max_index - 1)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
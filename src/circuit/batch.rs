@@ -0,0 +1,403 @@
+use std::collections::HashMap;
+
+use super::{bus_members, decode_bus, unknown_label, Bus, Circuit, Handle, NodeId, PhaseClock, SwitchId, Timing, Z3muError};
+use super::topology::{Contact, Csr};
+
+/// Number of independent input vectors simulated by one `BatchCircuit`
+pub const LANES: usize = 64;
+
+/// Bit-sliced copy of a `Circuit` that simulates `LANES` input vectors at once
+///
+/// Every node's powered state and every switch's position is a `u64` holding one bit per lane,
+/// so one propagation pass covers all lanes with word-wide operations.
+pub struct BatchCircuit {
+    coils: Csr<SwitchId>, // NodeId -> SwitchIds driven by coils on the node
+    contacts: Csr<Contact>, // NodeId -> Contacts touching the node
+    labels: HashMap<Handle, NodeId>,
+    timings: Vec<Timing>, // SwitchId -> Timing
+    ticks_per_step: u32,
+    g: NodeId,
+
+    // state
+    sources: Vec<u64>, // NodeId -> lanes in which the node is pulled high this step
+    held: Vec<u64>, // NodeId -> lanes in which the node is pulled high until released
+    clock: Option<PhaseClock>, // shared by all lanes
+    powered: Vec<u64>, // NodeId -> lanes in which the node was powered during the last tick
+    switch_positions: Vec<u64>, // SwitchId -> lanes in which the switch is active
+    held_for: Vec<u32>, // SwitchId * LANES + lane -> ticks the coil has disagreed with the switch
+
+    // scratch
+    step_sources: Vec<u64>, // sources of the current step, replayed on each of its ticks
+    energized: Vec<u64>,
+    worklist: Vec<NodeId>,
+}
+
+impl Circuit {
+
+    /// Copies the circuit into a batch simulator whose lanes all start from the current state,
+    /// including its held inputs and the phase of its clock
+    ///
+    /// Panics in electrical mode, which the batch simulator does not model.
+    pub fn batch(&self) -> BatchCircuit {
+        assert!(self.electrical.is_none(), "Circuits in electrical mode cannot be batched");
+        let num_switches = self.switches.len();
+        let timings: Vec<Timing> = (0..num_switches).map(|switch| self.armatures.timing(switch)).collect();
+        let held_for = if timings.iter().all(|timing| *timing == Timing::default()) {
+            Vec::new()
+        } else {
+            (0..num_switches).flat_map(|switch| [self.armatures.held_for(switch); LANES]).collect()
+        };
+        let mut held = vec![0; self.num_nodes];
+        for &node_id in self.held.values() {
+            held[node_id] = !0;
+        }
+        BatchCircuit {
+            coils: self.coils.clone(),
+            contacts: self.contacts.clone(),
+            labels: self.labels.clone(),
+            timings,
+            ticks_per_step: self.ticks_per_step,
+            g: self.labels[&handle!("G")],
+
+            sources: vec![0; self.num_nodes],
+            held,
+            clock: self.clock.clone(),
+            powered: self.powered.iter().map(|&powered| if powered { !0 } else { 0 }).collect(),
+            switch_positions: self.switch_positions.iter().map(|active| if active { !0 } else { 0 }).collect(),
+            held_for,
+
+            step_sources: Vec::new(),
+            energized: vec![0; num_switches],
+            worklist: Vec::new(),
+        }
+    }
+}
+
+impl BatchCircuit {
+
+    pub fn set(&mut self, lane: usize, handle: &Handle) {
+        self.try_set(lane, handle).unwrap_or_else(|err| panic!("{}", err));
+    }
+
+    /// Like `set`, but reports a missing label instead of panicking
+    pub fn try_set(&mut self, lane: usize, handle: &Handle) -> Result<(), Z3muError> {
+        assert!(lane < LANES);
+        let node_id = self.node_of(handle)?;
+        self.sources[node_id] |= 1 << lane;
+        Ok(())
+    }
+
+    pub fn set_bus(&mut self, lane: usize, bus: &Bus, k: i32) {
+        assert!(lane < LANES);
+        for (index, node_id) in bus_members(&self.labels, bus) {
+            if (k >> index) & 1 != 0 {
+                self.sources[node_id] |= 1 << lane;
+            }
+        }
+    }
+
+    /// Pull a node high in one lane on every step until it is released
+    pub fn hold(&mut self, lane: usize, handle: &Handle) {
+        self.try_hold(lane, handle).unwrap_or_else(|err| panic!("{}", err));
+    }
+
+    /// Like `hold`, but reports a missing label instead of panicking
+    pub fn try_hold(&mut self, lane: usize, handle: &Handle) -> Result<(), Z3muError> {
+        assert!(lane < LANES);
+        let node_id = self.node_of(handle)?;
+        self.held[node_id] |= 1 << lane;
+        Ok(())
+    }
+
+    pub fn release(&mut self, lane: usize, handle: &Handle) {
+        assert!(lane < LANES);
+        if let Ok(node_id) = self.node_of(handle) {
+            self.held[node_id] &= !(1 << lane);
+        }
+    }
+
+    pub fn inspect(&self, lane: usize, handle: &Handle) -> bool {
+        self.try_inspect(lane, handle).unwrap_or_else(|err| panic!("{} to inspect", err))
+    }
+
+    /// Like `inspect`, but reports a missing label instead of panicking
+    pub fn try_inspect(&self, lane: usize, handle: &Handle) -> Result<bool, Z3muError> {
+        assert!(lane < LANES);
+        let node_id = self.node_of(handle)?;
+        Ok(self.powered[node_id] >> lane & 1 != 0)
+    }
+
+    pub fn inspect_bus(&self, lane: usize, bus: &Bus) -> i32 {
+        assert!(lane < LANES);
+        let states: Vec<(i8, bool)> = bus_members(&self.labels, bus)
+            .into_iter()
+            .map(|(index, node_id)| (index, self.powered[node_id] >> lane & 1 != 0))
+            .collect();
+        decode_bus(&states)
+    }
+
+    /// Advance every lane by `ticks_per_step` ticks, holding the pending sources for all of them
+    ///
+    /// With a phase clock attached, the clock moves on to its next phase first.
+    pub fn step(&mut self) {
+        if let Some(clock) = &mut self.clock {
+            clock.advance();
+        }
+        let mut step_sources = std::mem::take(&mut self.step_sources);
+        step_sources.clone_from(&self.sources);
+        for tick in 0..self.ticks_per_step {
+            if tick > 0 {
+                self.sources.copy_from_slice(&step_sources);
+            }
+            self.tick();
+        }
+        self.step_sources = step_sources;
+    }
+
+    /// Advance every lane by one tick, consuming the pending sources
+    ///
+    /// Held inputs and, with a phase clock attached, the lines of the current phase are high as well.
+    pub fn tick(&mut self) {
+        // flood each node with the lanes that reach it, revisiting a node whenever it gains lanes
+        for (powered, (source, &held)) in self.powered.iter_mut().zip(self.sources.iter_mut().zip(&self.held)) {
+            *powered = std::mem::take(source) | held;
+        }
+        if let Some(clock) = &self.clock {
+            for &line in clock.lines() {
+                self.powered[line] = !0;
+            }
+        }
+        self.powered[self.g] = !0;
+        self.worklist.clear();
+        self.worklist.extend((0..self.powered.len()).filter(|&node| self.powered[node] != 0));
+        while let Some(node) = self.worklist.pop() {
+            for contact in self.contacts.row(node) {
+                let position = self.switch_positions[contact.switch];
+                let closed = if contact.closed_when { position } else { !position };
+                let gained = self.powered[node] & closed & !self.powered[contact.other];
+                if gained != 0 {
+                    self.powered[contact.other] |= gained;
+                    self.worklist.push(contact.other);
+                }
+            }
+        }
+
+        self.energized.fill(0);
+        for (node, &powered) in self.powered.iter().enumerate() {
+            if powered != 0 {
+                for &switch in self.coils.row(node) {
+                    self.energized[switch] |= powered;
+                }
+            }
+        }
+
+        for (switch, (position, &energized)) in self.switch_positions.iter_mut().zip(&self.energized).enumerate() {
+            if self.held_for.is_empty() {
+                *position = energized;
+                continue;
+            }
+            let timing = self.timings[switch];
+            let held_for = &mut self.held_for[switch * LANES..(switch + 1) * LANES];
+            let mut disagree = *position ^ energized;
+            held_for.iter_mut().enumerate().filter(|(lane, _)| disagree >> lane & 1 == 0).for_each(|(_, held)| *held = 0);
+            while disagree != 0 {
+                let lane = disagree.trailing_zeros() as usize;
+                disagree &= disagree - 1;
+                held_for[lane] += 1;
+                let delay = if energized >> lane & 1 != 0 { timing.pickup() } else { timing.dropout() };
+                if held_for[lane] >= delay {
+                    held_for[lane] = 0;
+                    *position ^= 1 << lane;
+                }
+            }
+        }
+    }
+
+    fn node_of(&self, handle: &Handle) -> Result<NodeId, Z3muError> {
+        self.labels.get(handle).copied().ok_or_else(|| unknown_label(handle))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::circuit::{CircuitBuilder, SubcircuitBuilder};
+    use crate::circuit::clock::PHASES;
+    use crate::common::gate;
+
+    #[test]
+    fn batch_gate_matches_scalar() {
+        let build = || CircuitBuilder::new()
+            .add_subcircuit(gate(bus!("Ab"), handle!("Ga"), bus!("Aa"), 0..=7))
+            .add_subcircuit(|mut scb| {
+                for i in 0..=7 {
+                    let ab = scb.coil(handle!("Ab", i), None);
+                    let aa = scb.label(handle!("Aa", i));
+                    scb.trace_all([ab, aa]);
+                }
+            })
+            .finalize();
+        let value = |lane: usize| (lane as i32 * 37 % 256) - 128;
+        let gated = |lane: usize| !lane.is_multiple_of(3);
+
+        let mut batch = build().batch();
+        for lane in 0..LANES {
+            batch.set_bus(lane, &bus!("Ab"), value(lane));
+            if gated(lane) {
+                batch.set(lane, &handle!("Ga"));
+            }
+        }
+        batch.step();
+        for lane in 0..LANES {
+            batch.set(lane, &handle!("S", 5));
+        }
+        batch.step();
+
+        for lane in 0..LANES {
+            let mut c = build();
+            c.set_bus(&bus!("Ab"), value(lane));
+            if gated(lane) {
+                c.set(&handle!("Ga"));
+            }
+            c.step();
+            c.set(&handle!("S", 5));
+            c.step();
+            let expected = if gated(lane) { value(lane) } else { 0 };
+            assert_eq!(c.inspect_bus(&bus!("Aa")), expected);
+            assert_eq!(batch.inspect_bus(lane, &bus!("Aa")), expected);
+        }
+    }
+
+    #[test]
+    fn batch_delays_match_scalar() {
+        let build = || CircuitBuilder::new().add_subcircuit(|mut scb| {
+            let input = scb.label("In");
+            let g = scb.label("G");
+            SubcircuitBuilder::chain(input, 0..3, |coil_pos, i| {
+                scb.coil_with_timing(handle!("Aa", i), coil_pos, Timing::new(2, 1 + i as u32));
+                let out = scb.label(handle!("out", i));
+                scb.trace(out);
+                scb.add_switch(handle!("aa", i), (g, out, None));
+                out
+            });
+        }).finalize();
+        // lane n holds the input for the first n % 9 steps
+        let held = |lane: usize, step: usize| step < lane % 9;
+
+        let mut batch = build().batch();
+        let mut scalars: Vec<Circuit> = (0..LANES).map(|_| build()).collect();
+        for step in 0..12 {
+            for (lane, scalar) in scalars.iter_mut().enumerate() {
+                if held(lane, step) {
+                    batch.set(lane, &handle!("In"));
+                    scalar.set(&handle!("In"));
+                }
+                scalar.step();
+            }
+            batch.step();
+            for (lane, scalar) in scalars.iter().enumerate() {
+                for i in 0..3 {
+                    assert_eq!(batch.inspect(lane, &handle!("out", i)), scalar.inspect(&handle!("out", i)));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn batch_ticks_consume_sources_like_scalar() {
+        let build = || CircuitBuilder::new().add_subcircuit(|mut scb| {
+            let (g, input) = (scb.label("G"), scb.label("In"));
+            scb.coil_with_timing(handle!("Aa", 0), input, Timing::new(2, 1));
+            let out = scb.label("out");
+            scb.trace(out);
+            scb.add_switch(handle!("aa", 0), (g, out, None));
+        }).finalize();
+        // lane 0 pulses the input once, lane 1 on every tick
+        let mut c = build();
+        let mut batch = c.batch();
+        let mut every_tick = build();
+        c.set(&handle!("In"));
+        batch.set(0, &handle!("In"));
+        for _ in 0..4 {
+            every_tick.set(&handle!("In"));
+            batch.set(1, &handle!("In"));
+            c.tick();
+            every_tick.tick();
+            batch.tick();
+            assert_eq!(batch.inspect(0, &handle!("In")), c.inspect(&handle!("In")));
+            assert_eq!(batch.inspect(0, &handle!("out")), c.inspect(&handle!("out")));
+            assert_eq!(batch.inspect(1, &handle!("out")), every_tick.inspect(&handle!("out")));
+        }
+        assert!(!c.inspect(&handle!("out")));
+        assert!(every_tick.inspect(&handle!("out")));
+    }
+
+    #[test]
+    fn batch_reports_unknown_labels() {
+        let mut batch = CircuitBuilder::new().finalize().batch();
+        let error = unknown_label(&handle!("Nope"));
+        assert_eq!(batch.try_set(0, &handle!("Nope")), Err(error.clone()));
+        assert_eq!(batch.try_hold(0, &handle!("Nope")), Err(error.clone()));
+        assert_eq!(batch.try_inspect(0, &handle!("Nope")), Err(error));
+    }
+
+    #[test]
+    fn batch_starts_from_current_state() {
+        let build = || CircuitBuilder::new().add_subcircuit(|mut scb| {
+            let (g, input) = (scb.label("G"), scb.label("In"));
+            scb.coil_with_timing(handle!("Aa", 0), input, Timing::new(3, 1));
+            scb.coil(handle!("Ab", 0), g);
+            let out = scb.label("out");
+            scb.trace(out);
+            scb.add_switch(handle!("aa", 0), (g, out, None));
+            scb.add_switch(handle!("ab", 0), (g, None, None));
+        }).finalize();
+        let mut c = build();
+        for _ in 0..2 {
+            c.set(&handle!("In"));
+            c.step();
+        }
+        // Ab_0 has picked up and Aa_0 is one tick away from doing so
+        let mut batch = c.batch();
+        assert_eq!(batch.switch_positions, vec![0, !0]);
+        batch.set(0, &handle!("In"));
+        c.set(&handle!("In"));
+        batch.step();
+        c.step();
+        batch.step();
+        c.step();
+        assert!(c.inspect(&handle!("out")));
+        assert!(batch.inspect(0, &handle!("out")));
+        assert!(!batch.inspect(1, &handle!("out")));
+    }
+
+    #[test]
+    fn batch_carries_held_inputs_and_clock() {
+        let build = || CircuitBuilder::new()
+            .with_phase_clock()
+            .add_subcircuit(|mut scb| {
+                let (g, input, s3) = (scb.label("G"), scb.label("In"), scb.label(handle!("S", 3)));
+                scb.coil(handle!("Aa", 0), input);
+                scb.coil(handle!("Ab", 0), s3);
+                let (_, mid, _) = scb.add_switch(handle!("aa", 0), (g, None, None));
+                let out = scb.label("out");
+                scb.trace(out);
+                scb.add_switch(handle!("ab", 0), (mid, out, None));
+            })
+            .finalize();
+        let mut c = build();
+        c.hold(&handle!("In"));
+        c.step();
+        let mut batch = c.batch();
+        batch.release(1, &handle!("In"));
+        let mut lit = false;
+        for _ in 0..2 * PHASES {
+            c.step();
+            batch.step();
+            lit |= c.inspect(&handle!("out"));
+            assert_eq!(batch.inspect(0, &handle!("out")), c.inspect(&handle!("out")));
+            assert!(!batch.inspect(1, &handle!("out")));
+        }
+        assert!(lit, "out never powered");
+    }
+}
@@ -0,0 +1,156 @@
+use log::*;
+
+use super::{Circuit, Handle, NodeId};
+
+/// Number of phases in one cycle of the Z3's clock
+pub const PHASES: u8 = 5;
+
+/// Drives the Z3's five-phase cycle onto the `S` labels, one phase per step
+///
+/// A label `S_i` is driven during every phase named by a digit of `i`, so `S_5` is high in phase
+/// 5 only while the composite line `S_123` is high in phases 1, 2 and 3.
+#[derive(Clone, Debug)]
+pub struct PhaseClock {
+    cycle: u64,
+    phase: u8, // 0 before the first step
+    lines: [Vec<NodeId>; PHASES as usize], // phase - 1 -> S labels driven in that phase
+}
+
+impl PhaseClock {
+    pub(super) fn new<'a>(labels: impl Iterator<Item = (&'a Handle, &'a NodeId)>) -> Self {
+        let mut lines: [Vec<NodeId>; PHASES as usize] = Default::default();
+        for (handle, node_id) in labels {
+            if handle.name != "S" || handle.sup.is_some() {
+                continue;
+            }
+            let Some(phases) = handle.index.and_then(PhaseClock::phases_of) else {
+                warn!("{} is not a phase line, the clock will not drive it", handle);
+                continue;
+            };
+            for phase in phases {
+                lines[phase as usize - 1].push(*node_id);
+            }
+        }
+        PhaseClock { cycle: 0, phase: 0, lines }
+    }
+
+    /// Phases named by the digits of a phase line index, e.g. 23 -> [2, 3]
+    fn phases_of(index: i8) -> Option<Vec<u8>> {
+        if index <= 0 {
+            return None;
+        }
+        let digits: Vec<u8> = index.to_string().bytes().map(|b| b - b'0').collect();
+        if digits.iter().all(|&phase| (1..=PHASES).contains(&phase)) {
+            Some(digits)
+        } else {
+            None
+        }
+    }
+
+    /// Number of completed cycles
+    pub fn cycle(&self) -> u64 {
+        self.cycle
+    }
+
+    /// Phase driven during the last step, from 1 to 5, or 0 before the first step
+    pub fn phase(&self) -> u8 {
+        self.phase
+    }
+
+    pub(super) fn jump_to(&mut self, cycle: u64, phase: u8) {
+        assert!(phase <= PHASES);
+        self.cycle = cycle;
+        self.phase = phase;
+    }
+
+    pub(super) fn advance(&mut self) {
+        if self.phase == PHASES {
+            self.phase = 1;
+            self.cycle += 1;
+        } else {
+            self.phase += 1;
+        }
+    }
+
+    /// Nodes driven during the current phase
+    pub(super) fn lines(&self) -> &[NodeId] {
+        self.lines_of(self.phase)
+    }
+
+    /// Nodes driven during `phase`, none for phase 0
+    pub(super) fn lines_of(&self, phase: u8) -> &[NodeId] {
+        if phase == 0 {
+            &[]
+        } else {
+            &self.lines[phase as usize - 1]
+        }
+    }
+}
+
+impl Circuit {
+
+    pub fn phase_clock(&self) -> Option<&PhaseClock> {
+        self.clock.as_ref()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::circuit::{Bus, CircuitBuilder};
+    use crate::common::gate_const;
+
+    #[test]
+    fn phases_of() {
+        assert_eq!(PhaseClock::phases_of(5), Some(vec![5]));
+        assert_eq!(PhaseClock::phases_of(123), Some(vec![1, 2, 3]));
+        assert_eq!(PhaseClock::phases_of(16), None);
+        assert_eq!(PhaseClock::phases_of(0), None);
+        assert_eq!(PhaseClock::phases_of(-3), None);
+    }
+
+    #[test]
+    fn drives_phase_lines() {
+        let lines = [1, 2, 3, 4, 5, 23, 123];
+        let mut c = CircuitBuilder::new()
+            .with_phase_clock()
+            .add_subcircuit(|mut scb| {
+                for i in lines {
+                    let s = scb.label(handle!("S", i));
+                    scb.trace(s);
+                }
+            })
+            .finalize();
+        assert_eq!(c.phase_clock().unwrap().phase(), 0);
+        for cycle in 0..2 {
+            for phase in 1..=PHASES {
+                c.step();
+                let clock = c.phase_clock().unwrap();
+                assert_eq!((clock.cycle(), clock.phase()), (cycle, phase));
+                for i in lines {
+                    let expected = i.to_string().contains(&phase.to_string());
+                    assert_eq!(c.inspect(&handle!("S", i)), expected, "S_{} in phase {}", i, phase);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn gate_runs_on_clock() {
+        let mut c = CircuitBuilder::new()
+            .with_phase_clock()
+            .add_subcircuit(gate_const(-4i8, handle!("Ei"), bus!("Ab"), 0..=7))
+            .add_subcircuit(|mut scb| {
+                let ab: Vec<NodeId> = (0..=7).map(|i| scb.label(handle!("Ab", i))).collect();
+                scb.trace_all(ab);
+            })
+            .finalize();
+        c.hold(&handle!("Ei"));
+        for _ in 0..4 {
+            c.step();
+            assert_eq!(c.inspect_bus(&bus!("Ab")), 0);
+        }
+        c.step();
+        assert_eq!(c.inspect_bus(&bus!("Ab")), -4);
+    }
+}
@@ -0,0 +1,369 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+use super::{Circuit, Handle, NodeId, Timing};
+use super::clock::PHASES;
+
+impl Circuit {
+
+    /// Emits standalone Rust source that simulates this circuit without interpreting the netlist
+    ///
+    /// The generated module defines a `State` struct starting from the circuit's current state.
+    /// Each tick is straight-line code: the powered nodes are boolean expressions over the switch
+    /// positions, evaluated along each tree of contacts from its leaves to a root and back, and
+    /// every switch's next position is the disjunction of the nodes its coils sit on. Only contacts
+    /// that form a loop are relaxed in a fixed-point loop. Inputs and outputs are reached through
+    /// `set`, `hold`, `release` and `inspect`, keyed by a `Handle` that the module declares with the
+    /// same fields as ours. Held inputs and the phase clock carry over into the generated state.
+    ///
+    /// Panics in electrical mode, which the generated code does not model.
+    pub fn to_rust(&self) -> String {
+        assert!(self.electrical.is_none(), "Circuits in electrical mode cannot be compiled to Rust");
+        let mut out = String::new();
+        self.write_rust(&mut out).expect("writing to a String cannot fail");
+        out
+    }
+
+    fn write_rust(&self, out: &mut String) -> std::fmt::Result {
+        let num_switches = self.switches.len();
+        let delayed = (0..num_switches).any(|switch| self.armatures.timing(switch) != Timing::default());
+        let mut labels: Vec<(&Handle, NodeId)> = self.labels.iter().map(|(handle, node_id)| (handle, *node_id)).collect();
+        labels.sort_by_key(|(handle, _)| handle.to_string());
+        let bools = |bits: &mut dyn Iterator<Item = bool>| bits.map(|b| b.to_string()).collect::<Vec<_>>().join(", ");
+        let pattern = |handle: &Handle| format!("({:?}, {:?}, {:?})", handle.name, handle.index, handle.sup);
+
+        writeln!(out, "// Generated by z3mu from a finalized circuit. Do not edit.")?;
+        writeln!(out)?;
+        writeln!(out, "pub const NODES: usize = {};", self.num_nodes)?;
+        writeln!(out, "pub const SWITCHES: usize = {};", num_switches)?;
+        writeln!(out, "pub const TICKS_PER_STEP: u32 = {};", self.ticks_per_step)?;
+        if let Some(clock) = &self.clock {
+            let phases: Vec<String> = (1..=PHASES)
+                .map(|phase| format!("&[{}]", clock.lines_of(phase).iter().map(NodeId::to_string).collect::<Vec<_>>().join(", ")))
+                .collect();
+            writeln!(out, "pub const PHASE_LINES: [&[usize]; {}] = [{}];", PHASES, phases.join(", "))?;
+        }
+        writeln!(out)?;
+        writeln!(out, "#[derive(Clone, Debug, PartialEq, Eq, Hash)]")?;
+        writeln!(out, "pub struct Handle {{")?;
+        writeln!(out, "    pub name: String,")?;
+        writeln!(out, "    pub index: Option<i8>,")?;
+        writeln!(out, "    pub sup: Option<u8>,")?;
+        writeln!(out, "}}")?;
+        writeln!(out)?;
+        writeln!(out, "impl Handle {{")?;
+        writeln!(out, "    pub fn new<T: Into<String>>(name: T, index: Option<i8>, sup: Option<u8>) -> Self {{")?;
+        writeln!(out, "        Handle {{ name: name.into(), index, sup }}")?;
+        writeln!(out, "    }}")?;
+        writeln!(out, "}}")?;
+        writeln!(out)?;
+        writeln!(out, "impl std::fmt::Display for Handle {{")?;
+        writeln!(out, "    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {{")?;
+        writeln!(out, "        write!(f, \"{{}}\", self.name)?;")?;
+        writeln!(out, "        if let Some(index) = self.index {{")?;
+        writeln!(out, "            write!(f, \"_{{}}\", index)?;")?;
+        writeln!(out, "        }}")?;
+        writeln!(out, "        if let Some(sup) = self.sup {{")?;
+        writeln!(out, "            write!(f, \"^{{}}\", sup)?;")?;
+        writeln!(out, "        }}")?;
+        writeln!(out, "        Ok(())")?;
+        writeln!(out, "    }}")?;
+        writeln!(out, "}}")?;
+        writeln!(out)?;
+        writeln!(out, "pub struct State {{")?;
+        writeln!(out, "    pub sources: [bool; NODES],")?;
+        writeln!(out, "    pub held: [bool; NODES],")?;
+        writeln!(out, "    pub powered: [bool; NODES],")?;
+        writeln!(out, "    pub positions: [bool; SWITCHES],")?;
+        if delayed {
+            writeln!(out, "    held_for: [u32; SWITCHES],")?;
+        }
+        if self.clock.is_some() {
+            writeln!(out, "    pub cycle: u64,")?;
+            writeln!(out, "    pub phase: u8,")?;
+        }
+        writeln!(out, "}}")?;
+        writeln!(out)?;
+        writeln!(out, "impl Default for State {{")?;
+        writeln!(out, "    fn default() -> Self {{")?;
+        writeln!(out, "        State {{")?;
+        writeln!(out, "            sources: [false; NODES],")?;
+        let mut held = vec![false; self.num_nodes];
+        for &node_id in self.held.values() {
+            held[node_id] = true;
+        }
+        writeln!(out, "            held: [{}],", bools(&mut held.into_iter()))?;
+        writeln!(out, "            powered: [{}],", bools(&mut self.powered.iter().copied()))?;
+        writeln!(out, "            positions: [{}],", bools(&mut self.switch_positions.iter()))?;
+        if delayed {
+            let held_for: Vec<String> = (0..num_switches).map(|switch| self.armatures.held_for(switch).to_string()).collect();
+            writeln!(out, "            held_for: [{}],", held_for.join(", "))?;
+        }
+        if let Some(clock) = &self.clock {
+            writeln!(out, "            cycle: {},", clock.cycle())?;
+            writeln!(out, "            phase: {},", clock.phase())?;
+        }
+        writeln!(out, "        }}")?;
+        writeln!(out, "    }}")?;
+        writeln!(out, "}}")?;
+        writeln!(out)?;
+        writeln!(out, "impl State {{")?;
+
+        writeln!(out, "    fn node(handle: &Handle, action: &str) -> usize {{")?;
+        writeln!(out, "        match (handle.name.as_str(), handle.index, handle.sup) {{")?;
+        for (handle, node_id) in &labels {
+            writeln!(out, "            {} => {},", pattern(handle), node_id)?;
+        }
+        writeln!(out, "            _ => panic!(\"Could not find node \\\"{{}}\\\" to {{}}\", handle, action),")?;
+        writeln!(out, "        }}")?;
+        writeln!(out, "    }}")?;
+        writeln!(out)?;
+        writeln!(out, "    pub fn set(&mut self, handle: &Handle) {{")?;
+        writeln!(out, "        self.sources[State::node(handle, \"set\")] = true;")?;
+        writeln!(out, "    }}")?;
+        writeln!(out)?;
+        writeln!(out, "    pub fn hold(&mut self, handle: &Handle) {{")?;
+        writeln!(out, "        self.held[State::node(handle, \"hold\")] = true;")?;
+        writeln!(out, "    }}")?;
+        writeln!(out)?;
+        writeln!(out, "    pub fn release(&mut self, handle: &Handle) {{")?;
+        writeln!(out, "        self.held[State::node(handle, \"release\")] = false;")?;
+        writeln!(out, "    }}")?;
+        writeln!(out)?;
+        writeln!(out, "    pub fn inspect(&self, handle: &Handle) -> bool {{")?;
+        writeln!(out, "        self.powered[State::node(handle, \"inspect\")]")?;
+        writeln!(out, "    }}")?;
+        writeln!(out)?;
+
+        writeln!(out, "    pub fn step(&mut self) {{")?;
+        if self.clock.is_some() {
+            writeln!(out, "        if self.phase == {} {{", PHASES)?;
+            writeln!(out, "            self.phase = 1;")?;
+            writeln!(out, "            self.cycle += 1;")?;
+            writeln!(out, "        }} else {{")?;
+            writeln!(out, "            self.phase += 1;")?;
+            writeln!(out, "        }}")?;
+            writeln!(out, "        for &line in PHASE_LINES[self.phase as usize - 1] {{")?;
+            writeln!(out, "            self.sources[line] = true;")?;
+            writeln!(out, "        }}")?;
+        }
+        writeln!(out, "        for _ in 0..TICKS_PER_STEP {{")?;
+        writeln!(out, "            self.tick();")?;
+        writeln!(out, "        }}")?;
+        writeln!(out, "        self.sources = [false; NODES];")?;
+        writeln!(out, "    }}")?;
+        writeln!(out)?;
+
+        writeln!(out, "    #[allow(unused_mut, unused_variables, clippy::all)]")?;
+        writeln!(out, "    pub fn tick(&mut self) {{")?;
+        writeln!(out, "        let s = &self.positions;")?;
+        writeln!(out, "        let mut p = self.sources;")?;
+        writeln!(out, "        for (p, held) in p.iter_mut().zip(&self.held) {{")?;
+        writeln!(out, "            *p |= *held;")?;
+        writeln!(out, "        }}")?;
+        writeln!(out, "        p[{}] = true;", self.labels[&handle!("G")])?;
+        self.write_contacts(out)?;
+
+        let mut drivers: Vec<Vec<NodeId>> = vec![Vec::new(); num_switches];
+        for node in 0..self.num_nodes {
+            for &switch in self.coils.row(node) {
+                drivers[switch].push(node);
+            }
+        }
+        writeln!(out, "        let e: [bool; SWITCHES] = [")?;
+        for nodes in &drivers {
+            let expr = if nodes.is_empty() {
+                "false".to_string()
+            } else {
+                nodes.iter().map(|node| format!("p[{}]", node)).collect::<Vec<_>>().join(" || ")
+            };
+            writeln!(out, "            {},", expr)?;
+        }
+        writeln!(out, "        ];")?;
+        if delayed {
+            for switch in 0..num_switches {
+                let timing = self.armatures.timing(switch);
+                writeln!(out, "        self.advance({}, e[{}], {}, {});", switch, switch, timing.pickup(), timing.dropout())?;
+            }
+        } else {
+            writeln!(out, "        self.positions = e;")?;
+        }
+        writeln!(out, "        self.powered = p;")?;
+        writeln!(out, "    }}")?;
+
+        if delayed {
+            writeln!(out)?;
+            writeln!(out, "    fn advance(&mut self, switch: usize, energized: bool, pickup: u32, dropout: u32) {{")?;
+            writeln!(out, "        if energized == self.positions[switch] {{")?;
+            writeln!(out, "            self.held_for[switch] = 0;")?;
+            writeln!(out, "            return;")?;
+            writeln!(out, "        }}")?;
+            writeln!(out, "        self.held_for[switch] += 1;")?;
+            writeln!(out, "        if self.held_for[switch] >= if energized {{ pickup }} else {{ dropout }} {{")?;
+            writeln!(out, "            self.held_for[switch] = 0;")?;
+            writeln!(out, "            self.positions[switch] = energized;")?;
+            writeln!(out, "        }}")?;
+            writeln!(out, "    }}")?;
+        }
+        writeln!(out, "}}")?;
+        Ok(())
+    }
+
+    /// Spreads power from the sources in `p` over the closed contacts
+    ///
+    /// Contacts between the same two nodes are merged into one edge closed by the disjunction of
+    /// their positions. Where the edges form a tree, power is gathered from the leaves towards the
+    /// root and then handed back down, one assignment per edge and direction.
+    fn write_contacts(&self, out: &mut String) -> std::fmt::Result {
+        let mut contacts: BTreeMap<(NodeId, NodeId), Vec<String>> = BTreeMap::new();
+        for (id, switch) in self.switches.iter().enumerate() {
+            for (branch, closed) in [(switch.no, format!("s[{}]", id)), (switch.nc, format!("!s[{}]", id))] {
+                if branch != switch.pole {
+                    contacts.entry((switch.pole.min(branch), switch.pole.max(branch))).or_default().push(closed);
+                }
+            }
+        }
+        let edges: Vec<(NodeId, NodeId, String)> = contacts
+            .into_iter()
+            .map(|((a, b), closed)| (a, b, if closed.len() == 1 { closed.join("") } else { format!("({})", closed.join(" || ")) }))
+            .collect();
+        let mut adjacent: Vec<Vec<(NodeId, usize)>> = vec![Vec::new(); self.num_nodes];
+        for (edge, (a, b, _)) in edges.iter().enumerate() {
+            adjacent[*a].push((*b, edge));
+            adjacent[*b].push((*a, edge));
+        }
+
+        let mut seen = vec![false; self.num_nodes];
+        for root in 0..self.num_nodes {
+            if seen[root] || adjacent[root].is_empty() {
+                continue;
+            }
+            // breadth-first, so that every node comes after its parent
+            seen[root] = true;
+            let mut order: Vec<(NodeId, Option<(NodeId, usize)>)> = vec![(root, None)];
+            let mut component_edges = Vec::new();
+            let mut next = 0;
+            while next < order.len() {
+                let node = order[next].0;
+                next += 1;
+                for &(neighbour, edge) in &adjacent[node] {
+                    component_edges.push(edge);
+                    if !seen[neighbour] {
+                        seen[neighbour] = true;
+                        order.push((neighbour, Some((node, edge))));
+                    }
+                }
+            }
+            component_edges.sort_unstable();
+            component_edges.dedup();
+
+            if component_edges.len() == order.len() - 1 {
+                for &(node, parent) in order.iter().rev() {
+                    if let Some((parent, edge)) = parent {
+                        writeln!(out, "        p[{}] |= {} && p[{}];", parent, edges[edge].2, node)?;
+                    }
+                }
+                for &(node, parent) in &order {
+                    if let Some((parent, edge)) = parent {
+                        writeln!(out, "        p[{}] |= {} && p[{}];", node, edges[edge].2, parent)?;
+                    }
+                }
+            } else {
+                let mut nodes: Vec<NodeId> = order.iter().map(|(node, _)| *node).collect();
+                nodes.sort_unstable();
+                let nodes: Vec<String> = nodes.iter().map(NodeId::to_string).collect();
+                writeln!(out, "        // the contacts between nodes {} form a loop, so they are relaxed until nothing changes", nodes.join(", "))?;
+                writeln!(out, "        loop {{")?;
+                writeln!(out, "            let mut changed = false;")?;
+                for &edge in &component_edges {
+                    let (a, b, closed) = &edges[edge];
+                    writeln!(out, "            if {} && p[{}] != p[{}] {{ p[{}] = true; p[{}] = true; changed = true; }}", closed, a, b, a, b)?;
+                }
+                writeln!(out, "            if !changed {{")?;
+                writeln!(out, "                break;")?;
+                writeln!(out, "            }}")?;
+                writeln!(out, "        }}")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::circuit::{Bus, CircuitBuilder, SubcircuitBuilder};
+    use crate::common::gate;
+
+    // These only look at the generated source; tests/codegen.rs compiles it and runs it
+    // against the interpreter
+
+    #[test]
+    fn gate_has_no_contact_loops() {
+        let c = CircuitBuilder::new()
+            .add_subcircuit(gate(bus!("Ab"), handle!("Ga"), bus!("Aa"), 0..=7))
+            .add_subcircuit(|mut scb| {
+                for i in 0..=7 {
+                    let ab = scb.coil(handle!("Ab", i), None);
+                    let aa = scb.label(handle!("Aa", i));
+                    scb.trace_all([ab, aa]);
+                }
+            })
+            .finalize();
+        let source = c.to_rust();
+        assert!(source.contains("pub struct State"));
+        assert!(!source.contains("loop"), "a gate has no loops of contacts");
+    }
+
+    #[test]
+    fn ticks_per_step_is_emitted() {
+        let c = CircuitBuilder::new()
+            .ticks_per_step(2)
+            .add_subcircuit(|mut scb| {
+                let input = scb.label("In");
+                let g = scb.label("G");
+                SubcircuitBuilder::chain(input, 0..4, |coil_pos, i| {
+                    scb.coil_with_timing(handle!("Aa", i), coil_pos, Timing::new(1 + i as u32, 2));
+                    let out = scb.label(handle!("out", i));
+                    scb.add_switch(handle!("aa", i), (g, out, None));
+                    out
+                });
+            })
+            .finalize();
+        assert!(c.to_rust().contains("pub const TICKS_PER_STEP: u32 = 2;"));
+    }
+
+    #[test]
+    fn contact_loops_are_marked() {
+        let c = CircuitBuilder::new()
+            .add_subcircuit(|mut scb| {
+                let g = scb.label("G");
+                let (x, y) = (scb.label("X"), scb.label("Y"));
+                for (coil, input) in [("Aa", "Ia"), ("Ab", "Ib"), ("Ac", "Ic")] {
+                    let input = scb.label(input);
+                    scb.coil(coil, input);
+                }
+                // X and Y are each fed from G and bridged to one another
+                scb.add_switch("aa", (g, x, None));
+                scb.add_switch("ab", (g, y, None));
+                scb.add_switch("ac", (x, y, None));
+            })
+            .finalize();
+        assert!(c.to_rust().contains("form a loop"));
+    }
+
+    #[test]
+    fn held_inputs_and_phase_clock_are_emitted() {
+        let mut c = CircuitBuilder::new()
+            .with_phase_clock()
+            .add_subcircuit(|mut scb| {
+                let input = scb.label("In");
+                scb.coil(handle!("Aa", 0), input);
+            })
+            .finalize();
+        c.hold(&handle!("In"));
+        let source = c.to_rust();
+        assert!(source.contains(&format!("pub const PHASE_LINES: [&[usize]; {}]", PHASES)));
+        assert!(source.contains("held: [") && source.contains("true"));
+    }
+}
@@ -0,0 +1,254 @@
+use std::collections::{HashMap, VecDeque};
+use log::*;
+
+use super::{Circuit, Handle, Switch, SwitchId};
+use super::timing::Armatures;
+use super::topology::BitSet;
+
+/// A repeating sequence of relay states entered while no inputs were arriving
+///
+/// Clock lines and inputs that stay held do not count as arriving inputs, but holding or
+/// releasing one does, and so does every tick with anything set for it, even the same labels
+/// as the tick before.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LimitCycle {
+    pub start_tick: u64, // first tick of the repeating sequence
+    pub period: u64, // ticks per repetition
+    pub relays: Vec<Handle>, // relays that move during the cycle, sorted by name
+}
+
+impl std::fmt::Display for LimitCycle {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "limit cycle of period {} from tick {} involving ", self.period, self.start_tick)?;
+        for (i, relay) in self.relays.iter().enumerate() {
+            write!(f, "{}{}", if i > 0 { ", " } else { "" }, relay)?;
+        }
+        Ok(())
+    }
+}
+
+/// Switch positions, armature delays and clock phase, which together decide every later quiet tick
+type RelayState = (BitSet, Vec<u32>, u8);
+
+/// Remembers the relay state of recent quiet ticks to spot when one repeats
+pub(super) struct CycleDetector {
+    max_period: usize,
+    seen: HashMap<RelayState, u64>, // state -> last tick it was seen
+    history: VecDeque<(RelayState, Vec<SwitchId>)>, // (state, switches moved) for recent ticks
+    prev_positions: BitSet,
+    found: Option<LimitCycle>,
+}
+
+impl CycleDetector {
+    fn new(max_period: usize, positions: &BitSet) -> Self {
+        CycleDetector {
+            max_period,
+            seen: HashMap::new(),
+            history: VecDeque::new(),
+            prev_positions: positions.clone(),
+            found: None,
+        }
+    }
+
+    fn reset(&mut self) {
+        self.seen.clear();
+        self.history.clear();
+        self.found = None;
+    }
+
+    /// Forgets everything seen so far, for when the circuit jumps to an unrelated state
+    pub(super) fn restart(&mut self, positions: &BitSet) {
+        self.reset();
+        self.prev_positions.clone_from(positions);
+    }
+
+    /// Records the state reached at the end of `tick`, with the clock phase it was in; `quiet` is
+    /// whether the tick had nothing set and no input held or released
+    ///
+    /// With a clock attached, a quiet circuit at rest repeats its state every cycle, so a repeat
+    /// only counts as a limit cycle if some relay moved in between.
+    pub(super) fn observe(&mut self, quiet: bool, tick: u64, (positions, armatures, phase): (&BitSet, &Armatures, u8), switches: &[Switch]) {
+        let moved: Vec<SwitchId> = positions.differences(&self.prev_positions).collect();
+        self.prev_positions.clone_from(positions);
+        if !quiet {
+            self.reset();
+        }
+        if self.found.is_some() {
+            return;
+        }
+
+        let state = (positions.clone(), armatures.state().to_vec(), phase);
+        if let Some(&prev_tick) = self.seen.get(&state) {
+            let period = tick - prev_tick;
+            let mut relays: Vec<Handle> = self.history
+                .iter()
+                .rev()
+                .take(period as usize - 1)
+                .flat_map(|(_, moved)| moved.iter())
+                .chain(&moved)
+                .map(|&switch| switches[switch].name.clone())
+                .collect();
+            relays.sort_by_key(|relay| relay.to_string());
+            relays.dedup();
+            if !relays.is_empty() {
+                let cycle = LimitCycle { start_tick: prev_tick + 1, period, relays };
+                warn!("Circuit entered a {}", cycle);
+                self.found = Some(cycle);
+                return;
+            }
+        }
+
+        self.seen.insert(state.clone(), tick);
+        self.history.push_back((state, moved));
+        if self.history.len() > self.max_period {
+            let (old_state, _) = self.history.pop_front().unwrap();
+            if self.seen.get(&old_state) == Some(&(tick - self.max_period as u64)) {
+                self.seen.remove(&old_state);
+            }
+        }
+    }
+}
+
+impl Circuit {
+
+    /// Starts watching for limit cycles of up to `max_period` ticks while no inputs arrive
+    pub fn detect_limit_cycles(&mut self, max_period: usize) {
+        assert!(max_period > 0);
+        self.cycles = Some(CycleDetector::new(max_period, &self.switch_positions));
+    }
+
+    /// The limit cycle the circuit is in, if cycle detection is on and one has been found since
+    /// the last input
+    pub fn limit_cycle(&self) -> Option<&LimitCycle> {
+        self.cycles.as_ref().and_then(|detector| detector.found.as_ref())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::circuit::{CircuitBuilder, Timing};
+
+    #[test]
+    fn buzzing_relay() {
+        let mut c = CircuitBuilder::new().add_subcircuit(|mut scb| {
+            let g = scb.label("G");
+            let (_, _, coil_node) = scb.add_switch("xy_-10", (g, None, None));
+            scb.coil("Xy_-10", Some(coil_node));
+        }).finalize();
+        c.detect_limit_cycles(16);
+        c.step();
+        assert_eq!(c.limit_cycle(), None);
+        c.step();
+        c.step();
+        let cycle = c.limit_cycle().expect("buzzer should be detected");
+        assert_eq!(cycle.period, 2);
+        assert_eq!(cycle.relays, vec![handle!("xy", -10)]);
+        assert_eq!(cycle.to_string(), "limit cycle of period 2 from tick 2 involving xy_-10");
+    }
+
+    #[test]
+    fn setting_inputs_every_step_is_not_quiet() {
+        let mut c = CircuitBuilder::new().add_subcircuit(|mut scb| {
+            let g = scb.label("G");
+            let (_, _, coil_node) = scb.add_switch("xy_-10", (g, None, None));
+            scb.coil("Xy_-10", Some(coil_node));
+            scb.label("In");
+        }).finalize();
+        c.detect_limit_cycles(16);
+        for _ in 0..8 {
+            c.set(&handle!("In"));
+            c.step();
+            assert_eq!(c.limit_cycle(), None);
+        }
+        c.step();
+        c.step();
+        assert!(c.limit_cycle().is_some());
+    }
+
+    #[test]
+    fn buzzing_relay_with_clock() {
+        let mut c = CircuitBuilder::new()
+            .with_phase_clock()
+            .ticks_per_step(2)
+            .add_subcircuit(|mut scb| {
+                let g = scb.label("G");
+                let (_, _, coil_node) = scb.add_switch("xy_-10", (g, None, None));
+                scb.coil("Xy_-10", Some(coil_node));
+                // a relay the clock keeps picked up in phase 5 only, besides a line nothing uses
+                let s5 = scb.label(handle!("S", 5));
+                scb.coil("Ab_0", s5);
+                scb.add_switch("ab_0", (g, None, None));
+                scb.label(handle!("S", 1));
+            })
+            .finalize();
+        c.detect_limit_cycles(32);
+        c.hold(&handle!("S", 1));
+        for _ in 0..12 {
+            c.step();
+        }
+        // the state only repeats once the clock is back in the same phase, one cycle of 10 ticks later
+        let cycle = c.limit_cycle().expect("buzzer should be detected with the clock running");
+        assert_eq!(cycle.period, 10);
+        assert_eq!(cycle.relays, vec![handle!("ab", 0), handle!("xy", -10)]);
+    }
+
+    #[test]
+    fn clock_alone_is_not_a_limit_cycle() {
+        let mut c = CircuitBuilder::new()
+            .with_phase_clock()
+            .add_subcircuit(|mut scb| {
+                let s3 = scb.label(handle!("S", 3));
+                scb.trace(s3);
+            })
+            .finalize();
+        c.detect_limit_cycles(16);
+        for _ in 0..20 {
+            c.step();
+        }
+        assert_eq!(c.limit_cycle(), None);
+    }
+
+    #[test]
+    fn delayed_ring_oscillator() {
+        // three relays in a ring, each releasing the next, plus a relay that never moves
+        let mut c = CircuitBuilder::new().add_subcircuit(|mut scb| {
+            let g = scb.label("G");
+            let coils: Vec<_> = (0..3).map(|i| scb.coil_with_timing(handle!("R", i), None, Timing::new(2, 1))).collect();
+            for i in 0..3 {
+                scb.add_switch(handle!("r", i), (g, None, coils[(i as usize + 1) % 3]));
+            }
+            let (_, no, _) = scb.add_switch("rx", (g, None, None));
+            scb.coil("Rx", no);
+        }).finalize();
+        c.detect_limit_cycles(32);
+        for _ in 0..20 {
+            c.step();
+        }
+        let cycle = c.limit_cycle().expect("ring should oscillate");
+        assert_eq!(cycle.relays, vec![handle!("r", 0), handle!("r", 1), handle!("r", 2)]);
+        assert!(cycle.period > 1);
+    }
+
+    #[test]
+    fn inputs_reset_detection() {
+        let mut c = CircuitBuilder::new().add_subcircuit(|mut scb| {
+            let g = scb.label("G");
+            let input = scb.label("In");
+            scb.coil("Ab_0", input);
+            scb.add_switch("ab_0", (g, None, None));
+        }).finalize();
+        c.detect_limit_cycles(16);
+        // toggling the relay from outside is not a limit cycle
+        for _ in 0..10 {
+            c.set(&handle!("In"));
+            c.step();
+            c.step();
+        }
+        assert_eq!(c.limit_cycle(), None);
+        for _ in 0..10 {
+            c.step();
+        }
+        assert_eq!(c.limit_cycle(), None);
+    }
+}
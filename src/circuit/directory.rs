@@ -0,0 +1,172 @@
+use std::collections::HashMap;
+
+use super::{Circuit, Handle, NodeId, Switch, SwitchId};
+use super::topology::Csr;
+
+/// Terminal of a changeover switch
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Terminal {
+    Pole,
+    No,
+    Nc,
+}
+
+impl std::fmt::Display for Terminal {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", match self {
+            Terminal::Pole => "pole",
+            Terminal::No => "no",
+            Terminal::Nc => "nc",
+        })
+    }
+}
+
+/// Everything known about one node of a finalized circuit
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct NodeInfo {
+    pub id: NodeId,
+    pub name: String, // as printed by diagnostics and exports
+    pub aliases: Vec<Handle>, // labels of the node, sorted by name
+    pub subcircuit: Option<String>, // subcircuit that created the node, None for `G`
+    pub terminals: Vec<(Handle, Terminal)>, // switch terminals on the node
+    pub coils: Vec<Handle>, // coils whose positive terminal is the node
+}
+
+impl std::fmt::Display for NodeInfo {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let join = |items: Vec<String>| if items.is_empty() { "none".to_string() } else { items.join(", ") };
+        write!(f, "{} (node {}", self.name, self.id)?;
+        if let Some(subcircuit) = &self.subcircuit {
+            write!(f, " in {}", subcircuit)?;
+        }
+        write!(f, "): aliases {}; switches {}; coils {}",
+               join(self.aliases.iter().map(Handle::to_string).collect()),
+               join(self.terminals.iter().map(|(switch, terminal)| format!("{}.{}", switch, terminal)).collect()),
+               join(self.coils.iter().map(Handle::to_string).collect()))
+    }
+}
+
+/// Reverse lookup from nodes to what touches them, built once by `CircuitBuilder::finalize`
+pub(super) struct NodeDirectory {
+    names: Vec<String>, // NodeId -> printable name
+    aliases: Csr<Handle>, // NodeId -> labels, sorted by name
+    owners: Vec<Option<usize>>, // NodeId -> index into subcircuits
+    subcircuits: Vec<String>,
+    terminals: Csr<(SwitchId, Terminal)>, // NodeId -> switch terminals, by SwitchId
+    coils: Csr<Handle>, // NodeId -> coils
+}
+
+impl NodeDirectory {
+    /// Names each node after its alphabetically first label, or after the first switch terminal
+    /// on it, e.g. `ab_0.no`, with `#<id>` appended where that alone is ambiguous
+    pub(super) fn new(labels: &HashMap<Handle, NodeId>, switches: &[Switch], coil_nodes: &[(Handle, NodeId)],
+                      owners: Vec<Option<usize>>, subcircuits: Vec<String>) -> Self {
+        let num_nodes = owners.len();
+        let mut sorted_labels: Vec<(String, &Handle, NodeId)> = labels
+            .iter()
+            .map(|(handle, &node_id)| (handle.to_string(), handle, node_id))
+            .collect();
+        sorted_labels.sort_by(|a, b| a.0.cmp(&b.0));
+        let aliases = Csr::from_pairs(num_nodes, sorted_labels.iter().map(|(_, handle, node_id)| (*node_id, (*handle).clone())).collect());
+        let terminals = Csr::from_pairs(num_nodes, switches.iter().enumerate().flat_map(|(id, switch)| [
+            (switch.pole, (id, Terminal::Pole)),
+            (switch.no, (id, Terminal::No)),
+            (switch.nc, (id, Terminal::Nc)),
+        ]).collect());
+        let coils = Csr::from_pairs(num_nodes, coil_nodes.iter().map(|(handle, node_id)| (*node_id, handle.clone())).collect());
+
+        let mut names: Vec<String> = (0..num_nodes).map(|node| match (aliases.row(node).first(), terminals.row(node).first()) {
+            (Some(alias), _) => alias.to_string(),
+            (None, Some((switch, terminal))) => format!("{}.{}", switches[*switch].name, terminal),
+            (None, None) => format!("#{}", node),
+        }).collect();
+        let mut uses: HashMap<String, usize> = HashMap::new();
+        for name in &names {
+            *uses.entry(name.clone()).or_default() += 1;
+        }
+        for (node, name) in names.iter_mut().enumerate() {
+            if uses[name.as_str()] > 1 {
+                name.push_str(&format!("#{}", node));
+            }
+        }
+
+        NodeDirectory { names, aliases, owners, subcircuits, terminals, coils }
+    }
+
+    pub(super) fn name(&self, node: NodeId) -> &str {
+        &self.names[node]
+    }
+
+    pub(super) fn first_alias(&self, node: NodeId) -> Option<&Handle> {
+        self.aliases.row(node).first()
+    }
+
+    pub(super) fn first_terminal(&self, node: NodeId) -> Option<(SwitchId, Terminal)> {
+        self.terminals.row(node).first().copied()
+    }
+}
+
+impl Circuit {
+
+    pub fn num_nodes(&self) -> usize {
+        self.num_nodes
+    }
+
+    /// Node a label refers to
+    pub fn node_of(&self, handle: &Handle) -> Option<NodeId> {
+        self.labels.get(handle).copied()
+    }
+
+    /// Aliases, creator and attached switches and coils of a node
+    pub fn node_info(&self, node: NodeId) -> NodeInfo {
+        let directory = &self.directory;
+        NodeInfo {
+            id: node,
+            name: directory.name(node).to_string(),
+            aliases: directory.aliases.row(node).to_vec(),
+            subcircuit: directory.owners[node].map(|owner| directory.subcircuits[owner].clone()),
+            terminals: directory.terminals
+                .row(node)
+                .iter()
+                .map(|&(switch, terminal)| (self.switches[switch].name.clone(), terminal))
+                .collect(),
+            coils: directory.coils.row(node).to_vec(),
+        }
+    }
+
+    /// Printable name of a node, as used by every diagnostic and export
+    pub fn node_name(&self, node: NodeId) -> String {
+        self.directory.name(node).to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::circuit::CircuitBuilder;
+
+    #[test]
+    fn describes_every_node() {
+        let c = CircuitBuilder::new()
+            .add_named_subcircuit("relay", |mut scb| {
+                let g = scb.label("G");
+                let coil = scb.coil(handle!("Ab", 0), None);
+                scb.add_switch(handle!("ab", 0), (g, None, coil));
+            })
+            .add_subcircuit(|mut scb| {
+                let shared = scb.label(handle!("Ab", 0));
+                let (pole, _, _) = scb.add_switch(handle!("ab", 0), (None, shared, None));
+                scb.add_switch(handle!("ab", 0), (pole, None, None));
+            })
+            .finalize();
+        let names: Vec<String> = (0..c.num_nodes()).map(|node| c.node_name(node)).collect();
+        assert_eq!(names, vec!["G", "Ab_0", "ab_0.no#2", "ab_0.pole", "ab_0.nc#4", "ab_0.no#5", "ab_0.nc#6"]);
+
+        let coil = c.node_info(c.node_of(&handle!("Ab", 0)).unwrap());
+        assert_eq!(coil.subcircuit.as_deref(), Some("relay"));
+        assert_eq!(coil.terminals, vec![(handle!("ab", 0), Terminal::Nc), (handle!("ab", 0), Terminal::No)]);
+        assert_eq!(coil.to_string(), "Ab_0 (node 1 in relay): aliases Ab_0; switches ab_0.nc, ab_0.no; coils Ab_0");
+        assert_eq!(c.node_info(3).subcircuit.as_deref(), Some("subcircuit 1"));
+        assert_eq!(c.node_info(0).to_string(), "G (node 0): aliases G; switches ab_0.pole; coils none");
+    }
+}
@@ -0,0 +1,222 @@
+use std::collections::HashSet;
+use log::*;
+
+use super::{Circuit, Handle, NodeId, SwitchId};
+use super::topology::ContactKind;
+
+/// Supply rail a node can be tied to in electrical mode
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Rail {
+    Positive,
+    Negative,
+}
+
+/// A tick in which closed contacts joined the positive and negative rails
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ShortCircuit {
+    pub tick: u64,
+    pub path: Vec<(Handle, ContactKind)>, // contacts from the negative rail to a supply, in order
+}
+
+impl std::fmt::Display for ShortCircuit {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "short circuit in tick {} through ", self.tick)?;
+        if self.path.is_empty() {
+            return write!(f, "a node tied to both rails");
+        }
+        for (i, (relay, kind)) in self.path.iter().enumerate() {
+            write!(f, "{}{} ({})", if i > 0 { ", " } else { "" }, relay, kind)?;
+        }
+        Ok(())
+    }
+}
+
+/// Coil whose negative terminal is a node of the circuit rather than the implicit return
+pub(super) struct Load {
+    pub(super) handle: Handle,
+    pub(super) pos: NodeId,
+    pub(super) neg: NodeId,
+    pub(super) switches: Vec<SwitchId>,
+}
+
+/// Rails, two-terminal coils and short-circuit reports of a circuit in electrical mode
+pub(super) struct Electrical {
+    positive: Vec<NodeId>,
+    negative: Vec<NodeId>,
+    loads: Vec<Load>,
+    root_grounded: Vec<bool>, // NodeId -> whether the component rooted here reaches the negative rail
+    root_supplied: Vec<bool>, // NodeId -> whether the component rooted here reaches G or the positive rail
+    shorts: Vec<ShortCircuit>,
+}
+
+impl Electrical {
+    pub(super) fn new(num_nodes: usize, ties: Vec<(NodeId, Rail)>, loads: Vec<Load>) -> Self {
+        let (positive, negative): (Vec<_>, Vec<_>) = ties.into_iter().partition(|(_, rail)| *rail == Rail::Positive);
+        Electrical {
+            positive: positive.into_iter().map(|(node, _)| node).collect(),
+            negative: negative.into_iter().map(|(node, _)| node).collect(),
+            loads,
+            root_grounded: vec![false; num_nodes],
+            root_supplied: vec![false; num_nodes],
+            shorts: Vec::new(),
+        }
+    }
+
+    pub(super) fn positive(&self) -> &[NodeId] {
+        &self.positive
+    }
+
+    pub(super) fn loads(&self) -> &[Load] {
+        &self.loads
+    }
+}
+
+impl Circuit {
+
+    /// Energizes two-terminal coils and reports shorts, once `step_a` has grouped the nodes into
+    /// components and marked those reaching a source
+    pub(super) fn step_electrical(&mut self) {
+        let g = self.labels[&handle!("G")];
+        let Some(el) = self.electrical.as_mut() else {
+            return;
+        };
+        let scratch = &mut self.two_phase;
+        el.root_grounded.fill(false);
+        for &node in &el.negative {
+            el.root_grounded[scratch.components.find(node)] = true;
+        }
+        el.root_supplied.fill(false);
+        for &node in el.positive.iter().chain([&g]) {
+            el.root_supplied[scratch.components.find(node)] = true;
+        }
+        for load in &el.loads {
+            if scratch.root_powered[scratch.components.find(load.pos)] && el.root_grounded[scratch.components.find(load.neg)] {
+                for &switch in &load.switches {
+                    scratch.next_positions.set(switch, true);
+                }
+            }
+        }
+
+        // a component holding both a supply and the negative rail is a short; inputs that are
+        // merely set are not supplies
+        let mut shorted_roots = HashSet::new();
+        let mut shorted = Vec::new();
+        for &node in &el.negative {
+            let root = scratch.components.find(node);
+            if el.root_supplied[root] && shorted_roots.insert(root) {
+                shorted.push(node);
+            }
+        }
+        if shorted.is_empty() {
+            return;
+        }
+        let supplies: HashSet<NodeId> = el.positive.iter().copied().chain([g]).collect();
+        for node in shorted {
+            let short = ShortCircuit { tick: self.ticks + 1, path: self.conducting_path(node, &supplies) };
+            warn!("{}", short);
+            self.electrical.as_mut().unwrap().shorts.push(short);
+        }
+    }
+
+    /// Contacts closed under the current switch positions that lead from `from` to one of `to`
+    fn conducting_path(&self, from: NodeId, to: &HashSet<NodeId>) -> Vec<(Handle, ContactKind)> {
+        self.closed_path(&self.switch_positions, from, to)
+            .unwrap_or_default()
+            .into_iter()
+            .map(|(switch, closed_when, _)| (self.switches[switch].name.clone(), ContactKind::from_closed_when(closed_when)))
+            .collect()
+    }
+
+    /// Shorts reported since the last `take_short_circuits`, or an empty slice outside electrical mode
+    pub fn short_circuits(&self) -> &[ShortCircuit] {
+        self.electrical.as_ref().map_or(&[], |el| &el.shorts)
+    }
+
+    pub fn take_short_circuits(&mut self) -> Vec<ShortCircuit> {
+        self.electrical.as_mut().map_or_else(Vec::new, |el| std::mem::take(&mut el.shorts))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::circuit::CircuitBuilder;
+
+    #[test]
+    fn short_through_contact_is_reported() {
+        let mut c = CircuitBuilder::new()
+            .electrical()
+            .add_subcircuit(|mut scb| {
+                let (g, input) = (scb.label("G"), scb.label("In"));
+                scb.coil(handle!("Ab", 0), input);
+                let (_, no, _) = scb.add_switch("ab_0", (g, None, None));
+                scb.tie(no, Rail::Negative);
+            })
+            .finalize();
+        c.step();
+        assert!(c.short_circuits().is_empty());
+        c.set(&handle!("In"));
+        c.step();
+        c.step();
+        let shorts = c.take_short_circuits();
+        assert_eq!(shorts.len(), 1);
+        assert_eq!(shorts[0].path, vec![(handle!("ab", 0), ContactKind::No)]);
+        assert!(c.short_circuits().is_empty());
+    }
+
+    #[test]
+    fn two_terminal_coil_needs_a_return() {
+        let mut c = CircuitBuilder::new()
+            .electrical()
+            .add_subcircuit(|mut scb| {
+                let (g, input, minus) = (scb.label("G"), scb.label("In"), scb.label("minus"));
+                scb.tie(minus, Rail::Negative);
+                scb.coil(handle!("Ab", 0), input);
+                let (_, neg) = scb.coil_between(handle!("Bb", 0), g, None);
+                scb.add_switch("ab_0", (minus, neg, None));
+                scb.add_switch("bb_0", (g, None, None));
+            })
+            .finalize();
+        c.step();
+        assert!(!c.is_picked_up(&handle!("bb", 0)));
+        c.hold(&handle!("In"));
+        c.step();
+        assert!(c.is_picked_up(&handle!("ab", 0)) && !c.is_picked_up(&handle!("bb", 0)));
+        c.step();
+        assert!(c.is_picked_up(&handle!("bb", 0)));
+        assert!(c.short_circuits().is_empty());
+    }
+
+    #[test]
+    fn shorts_lead_to_a_supply() {
+        let mut c = CircuitBuilder::new()
+            .electrical()
+            .add_subcircuit(|mut scb| {
+                let (input, plus, minus) = (scb.label("In"), scb.label("plus"), scb.label("minus"));
+                scb.tie(plus, Rail::Positive);
+                scb.tie(minus, Rail::Negative);
+                scb.coil(handle!("Ab", 0), input);
+                scb.coil(handle!("Cd", 0), None);
+                scb.add_switch("cd_0", (minus, None, input));
+                scb.add_switch("ab_0", (input, plus, None));
+            })
+            .finalize();
+        c.hold(&handle!("In"));
+        c.step();
+        assert!(c.short_circuits().is_empty(), "an input joined to the negative rail is not a short");
+        c.step();
+        let shorts = c.take_short_circuits();
+        assert_eq!(shorts[0].path, vec![(handle!("cd", 0), ContactKind::Nc), (handle!("ab", 0), ContactKind::No)]);
+    }
+
+    #[test]
+    #[should_panic(expected = "CircuitBuilder::electrical")]
+    fn rails_need_electrical_mode() {
+        CircuitBuilder::new()
+            .add_subcircuit(|mut scb| {
+                let minus = scb.label("minus");
+                scb.tie(minus, Rail::Negative);
+            })
+            .finalize();
+    }
+}
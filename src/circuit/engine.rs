@@ -0,0 +1,295 @@
+use super::{Circuit, Handle, NodeId, SwitchId};
+use super::topology::{BitSet, UnionFind};
+
+/// Propagation strategy used by `Circuit::step`
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum Engine {
+    /// Flood the whole circuit and rebuild every connection on each step (`step_a`/`step_b`)
+    #[default]
+    TwoPhase,
+    /// Only re-flood the regions touched by changed switches or sources
+    EventDriven,
+}
+
+/// Scratch space for `step_a`/`step_b`, allocated once so that stepping does not allocate
+#[derive(Default)]
+pub(super) struct TwoPhaseState {
+    pub(super) components: UnionFind,
+    pub(super) root_powered: Vec<bool>, // NodeId -> whether the component rooted here holds a source
+    pub(super) next_positions: BitSet, // SwitchId -> position after step_b
+}
+
+impl TwoPhaseState {
+    pub(super) fn new(num_nodes: usize, num_switches: usize) -> Self {
+        TwoPhaseState {
+            components: UnionFind::new(num_nodes),
+            root_powered: vec![false; num_nodes],
+            next_positions: BitSet::new(num_switches),
+        }
+    }
+}
+
+/// Bookkeeping carried between steps by the event-driven engine
+#[derive(Default)]
+pub(super) struct EventState {
+    energized: Vec<u32>, // SwitchId -> number of powered coils driving the switch
+    dirty: Vec<NodeId>, // nodes whose contacts changed since the last step
+    last_sources: Vec<NodeId>,
+    stale: bool, // energized must be rebuilt from scratch
+
+    // scratch space reused between steps
+    seen: Vec<bool>,
+    is_source: Vec<bool>,
+    stack: Vec<NodeId>,
+    component: Vec<NodeId>,
+    touched: Vec<SwitchId>,
+    waiting: Vec<SwitchId>, // switches whose coil disagrees with their position
+    queued: Vec<bool>, // SwitchId -> already advanced this tick
+}
+
+impl EventState {
+    pub(super) fn invalidate(&mut self) {
+        self.stale = true;
+    }
+
+    fn rebuild(&mut self, powered: &mut [bool], num_switches: usize) {
+        powered.fill(false);
+        self.energized = vec![0; num_switches];
+        self.seen = vec![false; powered.len()];
+        self.is_source = vec![false; powered.len()];
+        self.dirty.clear();
+        self.dirty.extend(0..powered.len());
+        self.touched.clear();
+        self.touched.extend(0..num_switches);
+        self.waiting.clear();
+        self.queued = vec![false; num_switches];
+        self.stale = false;
+    }
+}
+
+impl Circuit {
+
+    pub fn engine(&self) -> Engine {
+        self.engine
+    }
+
+    pub fn set_engine(&mut self, engine: Engine) {
+        self.engine = engine;
+        self.event.invalidate();
+    }
+
+    /// Event-driven equivalent of `step_a` followed by `step_b`
+    ///
+    /// Only the connected regions containing a moved switch or a new or expired source are
+    /// re-flooded, and only switches whose coil energization changed are moved.
+    pub(super) fn step_event(&mut self) {
+        if !self.initialized {
+            self.flood();
+            self.move_switches();
+            return;
+        }
+        let g = self.labels[&handle!("G")];
+        let ev = &mut self.event;
+        if ev.stale {
+            ev.rebuild(&mut self.powered, self.switches.len());
+        }
+
+        // last step's sources must be re-flooded in case they are no longer powered
+        ev.dirty.append(&mut ev.last_sources);
+        std::mem::swap(&mut ev.last_sources, &mut self.sources);
+        let sources = &mut ev.last_sources;
+        sources.push(g);
+        for &source in sources.iter() {
+            ev.is_source[source] = true;
+        }
+        ev.dirty.extend(sources.iter().copied());
+
+        // re-flood every component containing a dirty node
+        while let Some(seed) = ev.dirty.pop() {
+            if ev.seen[seed] {
+                continue;
+            }
+            ev.seen[seed] = true;
+            ev.stack.push(seed);
+            let start = ev.component.len();
+            let mut powered = false;
+            while let Some(node) = ev.stack.pop() {
+                ev.component.push(node);
+                powered |= ev.is_source[node];
+                for contact in self.contacts.row(node) {
+                    if self.switch_positions.get(contact.switch) == contact.closed_when && !ev.seen[contact.other] {
+                        ev.seen[contact.other] = true;
+                        ev.stack.push(contact.other);
+                    }
+                }
+            }
+            for &node in &ev.component[start..] {
+                if self.powered[node] == powered {
+                    continue;
+                }
+                self.powered[node] = powered;
+                for &switch in self.coils.row(node) {
+                    let count = &mut ev.energized[switch];
+                    if powered {
+                        *count += 1;
+                    } else {
+                        *count -= 1;
+                    }
+                    ev.touched.push(switch);
+                }
+            }
+        }
+        for node in ev.component.drain(..) {
+            ev.seen[node] = false;
+        }
+        for &source in sources.iter() {
+            ev.is_source[source] = false;
+        }
+
+        for (node_id, b) in &mut self.traces {
+            *b = self.powered[*node_id];
+        }
+
+        // advance the switches whose energization changed or that are still waiting on a delay
+        ev.touched.append(&mut ev.waiting);
+        let queued = &mut ev.queued;
+        ev.touched.retain(|&switch_id| !std::mem::replace(&mut queued[switch_id], true));
+        for switch_id in ev.touched.drain(..) {
+            ev.queued[switch_id] = false;
+            let energized = ev.energized[switch_id] > 0;
+            let position = self.switch_positions.get(switch_id);
+            let next_position = self.armatures.advance(switch_id, energized, position);
+            if next_position != energized {
+                ev.waiting.push(switch_id);
+            }
+            if next_position != position {
+                self.switch_positions.set(switch_id, next_position);
+                let switch = &self.switches[switch_id];
+                ev.dirty.extend([switch.pole, switch.no, switch.nc]);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::circuit::{CircuitBuilder, Timing};
+
+    /// Linear congruential generator so that random netlists are reproducible
+    struct Lcg(u64);
+
+    impl Lcg {
+        fn below(&mut self, bound: usize) -> usize {
+            self.0 = self.0.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+            (self.0 >> 33) as usize % bound
+        }
+    }
+
+    /// Builds a netlist of randomly wired relays with input labels `in_0` to `in_15`, optionally
+    /// giving the relays random pickup and dropout delays
+    fn random_netlist(seed: u64, relays: usize, contacts_per_relay: usize, delays: bool) -> Circuit {
+        let mut rng = Lcg(seed);
+        CircuitBuilder::new().add_subcircuit(|mut scb| {
+            let g = scb.label("G");
+            let mut nodes: Vec<NodeId> = (0..16).map(|i| scb.label(handle!("in", i))).collect();
+            nodes.push(g);
+            nodes.extend((0..relays * contacts_per_relay).map(|_| scb.node(None)));
+            for relay in 0..relays {
+                let (name, index) = (relay / 100, (relay % 100) as i8);
+                let coil_pos = nodes[rng.below(nodes.len())];
+                let timing = if delays {
+                    Timing::new(1 + rng.below(3) as u32, 1 + rng.below(3) as u32)
+                } else {
+                    Timing::default()
+                };
+                scb.coil_with_timing(Handle::new(format!("R{}", name), Some(index), None), coil_pos, timing);
+                for _ in 0..contacts_per_relay {
+                    let pole = nodes[rng.below(nodes.len())];
+                    let no = (rng.below(2) == 0).then(|| nodes[rng.below(nodes.len())]);
+                    let nc = (rng.below(2) == 0).then(|| nodes[rng.below(nodes.len())]);
+                    scb.add_switch(Handle::new(format!("r{}", name), Some(index), None), (pole, no, nc));
+                }
+            }
+        }).finalize()
+    }
+
+    /// One step the way `Circuit` worked before the CSR redesign: rebuild adjacency lists from
+    /// the switch positions and flood them from the sources. Returns the powered nodes.
+    fn legacy_step(c: &Circuit, positions: &mut Vec<bool>, sources: &[NodeId]) -> Vec<bool> {
+        let mut connections = vec![Vec::new(); c.num_nodes];
+        for (active, switch) in positions.iter().zip(&c.switches) {
+            let branch = if *active { switch.no } else { switch.nc };
+            connections[switch.pole].push(branch);
+            connections[branch].push(switch.pole);
+        }
+        let mut visited = vec![false; c.num_nodes];
+        let mut next_positions = vec![false; c.switches.len()];
+        let mut stack = sources.to_vec();
+        stack.push(c.labels[&handle!("G")]);
+        while let Some(node) = stack.pop() {
+            if visited[node] {
+                continue;
+            }
+            visited[node] = true;
+            for &switch in c.coils.row(node) {
+                next_positions[switch] = true;
+            }
+            stack.extend(connections[node].iter().filter(|other| !visited[**other]));
+        }
+        *positions = next_positions;
+        visited
+    }
+
+    fn random_inputs(rng: &mut Lcg) -> Vec<Handle> {
+        (0..rng.below(4)).map(|_| handle!("in", rng.below(16) as i8)).collect()
+    }
+
+    #[test]
+    fn engines_match_legacy_on_random_netlists() {
+        for seed in 0..8 {
+            let mut two_phase = random_netlist(seed, 40, 6, false);
+            two_phase.set_engine(Engine::TwoPhase);
+            let mut event_driven = random_netlist(seed, 40, 6, false);
+            event_driven.set_engine(Engine::EventDriven);
+            let mut legacy_positions = vec![false; two_phase.switches.len()];
+            let mut rng = Lcg(seed);
+            for _ in 0..50 {
+                let inputs = random_inputs(&mut rng);
+                let sources: Vec<NodeId> = inputs.iter().map(|h| two_phase.labels[h]).collect();
+                for input in &inputs {
+                    two_phase.set(input);
+                    event_driven.set(input);
+                }
+                let legacy_powered = legacy_step(&two_phase, &mut legacy_positions, &sources);
+                two_phase.step();
+                event_driven.step();
+                assert_eq!(two_phase.powered, legacy_powered);
+                assert_eq!(event_driven.powered, legacy_powered);
+                assert_eq!(two_phase.switch_positions.iter().collect::<Vec<_>>(), legacy_positions);
+                assert_eq!(event_driven.switch_positions, two_phase.switch_positions);
+            }
+        }
+    }
+
+    #[test]
+    fn engines_agree_with_delays() {
+        for seed in 0..8 {
+            let mut two_phase = random_netlist(seed, 40, 6, true);
+            two_phase.set_engine(Engine::TwoPhase);
+            let mut event_driven = random_netlist(seed, 40, 6, true);
+            event_driven.set_engine(Engine::EventDriven);
+            let mut rng = Lcg(seed);
+            for _ in 0..50 {
+                for input in random_inputs(&mut rng) {
+                    two_phase.set(&input);
+                    event_driven.set(&input);
+                }
+                two_phase.step();
+                event_driven.step();
+                assert_eq!(event_driven.powered, two_phase.powered);
+                assert_eq!(event_driven.switch_positions, two_phase.switch_positions);
+            }
+        }
+    }
+}
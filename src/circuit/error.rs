@@ -0,0 +1,148 @@
+use super::{Bus, Handle, NodeId, Timing, ValidationReport};
+
+/// Mistakes in a netlist or in how a circuit is driven, reported by the `try_` variants of the
+/// builder and simulator methods instead of panicking
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Z3muError {
+    InvalidHandle { text: String }, // malformed handle text, or a name containing `_` or `^`
+    DuplicateCoil { coil: Handle, node: NodeId, subcircuit: String }, // coil label already on `node`
+    ConflictingTiming { coil: Handle, timing: Timing, existing: Timing, subcircuit: String }, // coil added again with another timing, or coils of one relay timed differently
+    UnknownLabel { handle: Handle, subcircuit: Option<String> }, // subcircuit is the scope in the handle's path
+    UnknownBus { bus: Bus, subcircuit: Option<String> },
+    InvalidScope { scope: String }, // empty, or containing `_`, `^`, `/`, `#` or whitespace
+    DuplicateScope { scope: String }, // path of a scope that was already built
+    UnboundPort { instance: String, subcircuit: String, port: String },
+    UnknownPort { instance: String, subcircuit: String, port: String }, // binding for a port that is not declared
+    PortMismatch { instance: String, subcircuit: String, port: String }, // bus bound to a single port, or used with an undeclared index
+    UndrivenPort { instance: String, subcircuit: String, port: String }, // output with no coil or contact of the instance on it
+    Validation(ValidationReport), // a `strict` builder's netlist has errors
+}
+
+impl std::fmt::Display for Z3muError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Z3muError::InvalidHandle { text } => write!(f, "\"{}\" is not a valid handle", text),
+            Z3muError::DuplicateCoil { coil, node, subcircuit } =>
+                write!(f, "coil {} in {} is already on node {} and cannot be moved", coil, subcircuit, node),
+            Z3muError::ConflictingTiming { coil, timing, existing, subcircuit } =>
+                write!(f, "coil {} in {} is already timed {}/{} ticks, not {}/{}", coil, subcircuit,
+                       existing.pickup(), existing.dropout(), timing.pickup(), timing.dropout()),
+            Z3muError::UnknownLabel { handle, subcircuit: None } => write!(f, "Could not find node \"{}\"", handle),
+            Z3muError::UnknownLabel { handle, subcircuit: Some(subcircuit) } => write!(f, "Could not find node \"{}\" in {}", handle, subcircuit),
+            Z3muError::UnknownBus { bus, subcircuit: None } => write!(f, "Could not find bus \"{}\"", bus),
+            Z3muError::UnknownBus { bus, subcircuit: Some(subcircuit) } => write!(f, "Could not find bus \"{}\" in {}", bus, subcircuit),
+            Z3muError::InvalidScope { scope } => write!(f, "\"{}\" is not a valid scope name", scope),
+            Z3muError::DuplicateScope { scope } => write!(f, "scope {} is already in use", scope),
+            Z3muError::UnboundPort { instance, subcircuit, port } =>
+                write!(f, "port {} of {} ({}) is not connected", port, instance, subcircuit),
+            Z3muError::UnknownPort { instance, subcircuit, port } => write!(f, "{} ({}) has no port {}", instance, subcircuit, port),
+            Z3muError::PortMismatch { instance, subcircuit, port } =>
+                write!(f, "port {} of {} ({}) is bound or used in a way its declaration does not allow", port, instance, subcircuit),
+            Z3muError::UndrivenPort { instance, subcircuit, port } =>
+                write!(f, "output {} of {} ({}) is not driven by any coil or contact", port, instance, subcircuit),
+            Z3muError::Validation(report) => write!(f, "Circuit failed validation:\n{}", report),
+        }
+    }
+}
+
+impl std::error::Error for Z3muError {}
+
+/// Why a snapshot, session log or netlist could not be read or written, with parse errors located
+/// by 1-based line and, where the format has them, column
+#[derive(Debug)]
+pub enum FileError {
+    Io(std::io::Error),
+    Version { found: u32, supported: u32 }, // written by an unsupported version
+    Parse { line: usize, column: Option<usize>, message: String },
+}
+
+impl std::fmt::Display for FileError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FileError::Io(err) => write!(f, "{}", err),
+            FileError::Version { found, supported } => write!(f, "version {} is not supported (expected {})", found, supported),
+            FileError::Parse { line, column: None, message } => write!(f, "line {}: {}", line, message),
+            FileError::Parse { line, column: Some(column), message } => write!(f, "line {}, column {}: {}", line, column, message),
+        }
+    }
+}
+
+impl std::error::Error for FileError {}
+
+impl From<std::io::Error> for FileError {
+    fn from(err: std::io::Error) -> Self {
+        FileError::Io(err)
+    }
+}
+
+impl FileError {
+    pub(super) fn at_line(line: usize, message: String) -> Self {
+        FileError::Parse { line, column: None, message }
+    }
+}
+
+/// Checks the `z3mu <format> <version>` header of a versioned text format and returns the
+/// remaining non-blank lines, trimmed and numbered from 1
+pub(super) fn versioned_lines<'t>(text: &'t str, format: &str, supported: u32) -> Result<impl Iterator<Item = (usize, &'t str)>, FileError> {
+    let mut lines = text.lines().enumerate().map(|(i, line)| (i + 1, line.trim())).filter(|(_, line)| !line.is_empty());
+    let header = lines.next();
+    let version = header
+        .and_then(|(_, header)| header.strip_prefix("z3mu ")?.strip_prefix(format)?.strip_prefix(' ')?.parse().ok())
+        .ok_or_else(|| FileError::at_line(header.map_or(1, |(line, _)| line),
+                                          format!("expected a \"z3mu {} <version>\" header", format)))?;
+    if version != supported {
+        return Err(FileError::Version { found: version, supported });
+    }
+    Ok(lines)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::circuit::{CircuitBuilder, Timing};
+
+    #[test]
+    fn reports_mistakes_without_panicking() {
+        assert_eq!("Ba_-3^1".parse(), Ok(handle!("Ba", -3, 1)));
+        assert_eq!("Ba_x".parse::<Handle>(), Err(Z3muError::InvalidHandle { text: "Ba_x".into() }));
+        assert!(Handle::try_new("a_b", None, None).is_err());
+
+        let mut errors = Vec::new();
+        let mut c = CircuitBuilder::new()
+            .add_named_subcircuit("fig1", |mut scb| {
+                let input = scb.label("In");
+                scb.coil(handle!("Ab", 0), None);
+                errors.push(scb.try_coil(handle!("Ab", 0), input).unwrap_err());
+                assert!(scb.try_coil(handle!("Ab", 0), None).is_ok());
+                scb.coil_with_timing(handle!("Ab", 1), None, Timing::new(2, 1));
+                assert!(scb.try_coil(handle!("Ab", 1), None).is_ok());
+                assert!(scb.try_coil_with_timing(handle!("Ab", 1), None, Timing::new(2, 1)).is_ok());
+                errors.push(scb.try_coil_with_timing(handle!("Ab", 1), None, Timing::default()).unwrap_err());
+            })
+            .finalize();
+        CircuitBuilder::new()
+            .electrical()
+            .add_named_subcircuit("loads", |mut scb| {
+                scb.coil_between(handle!("Bb", 0), None, None);
+                errors.push(scb.try_coil_between(handle!("Bb", 0), None, None).unwrap_err());
+            })
+            .finalize();
+        assert_eq!(errors[2].to_string(), "coil Bb_0 in loads is already on node 1 and cannot be moved");
+        assert_eq!(errors[0].to_string(), "coil Ab_0 in fig1 is already on node 2 and cannot be moved");
+        assert_eq!(errors[1].to_string(), "coil Ab_1 in fig1 is already timed 2/1 ticks, not 1/1");
+
+        assert_eq!(c.try_set(&handle!("Nope")), Err(Z3muError::UnknownLabel { handle: handle!("Nope"), subcircuit: None }));
+        assert_eq!(c.try_inspect(&handle!("Nope")), Err(Z3muError::UnknownLabel { handle: handle!("Nope"), subcircuit: None }));
+        assert_eq!(c.try_set(&handle!("fig1/Nope")).unwrap_err().to_string(), "Could not find node \"fig1/Nope\" in fig1");
+        for result in [c.try_hold(&handle!("Nope")), c.try_trace(&handle!("Nope")), c.try_untrace(&handle!("Nope"))] {
+            assert_eq!(result, Err(Z3muError::UnknownLabel { handle: handle!("Nope"), subcircuit: None }));
+        }
+        assert_eq!(c.try_explain(&handle!("Nope")), Err(Z3muError::UnknownLabel { handle: handle!("Nope"), subcircuit: None }));
+        assert_eq!(c.try_why_not(&handle!("Nope")), Err(Z3muError::UnknownLabel { handle: handle!("Nope"), subcircuit: None }));
+        assert_eq!(c.try_set_bus(&bus!("Xy"), 3), Err(Z3muError::UnknownBus { bus: bus!("Xy"), subcircuit: None }));
+        assert_eq!(Bus::try_new("Ab_0", None), Err(Z3muError::InvalidHandle { text: "Ab_0".into() }));
+        c.try_set(&handle!("In")).unwrap();
+        c.step();
+        assert_eq!(c.try_inspect(&handle!("In")), Ok(true));
+    }
+}
@@ -0,0 +1,252 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+
+use super::{unknown_label, Circuit, CircuitBuilder, Handle, NodeId, SwitchId, Z3muError};
+use super::topology::{BitSet, ContactKind};
+
+/// Chain of closed contacts that carried power to a node during the last tick
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ConductionPath {
+    pub source: String, // `G` or a node set as a source for the tick
+    pub links: Vec<Link>, // contacts from the source to the node, in order
+}
+
+/// One closed contact on a `ConductionPath` and the node it leads to
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Link {
+    pub switch: Handle,
+    pub contact: ContactKind,
+    pub node: String,
+}
+
+impl std::fmt::Display for ConductionPath {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.source)?;
+        for link in &self.links {
+            write!(f, " -[{} {}]- {}", link.switch, link.contact, link.node)?;
+        }
+        Ok(())
+    }
+}
+
+/// A way power could have reached a node during the last tick, cut off by an open contact
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BlockedPath {
+    pub path: ConductionPath, // from a powered node to the target, starting with the open contact
+    pub coils: Vec<Handle>, // coils operating the switch of the open contact
+}
+
+impl std::fmt::Display for BlockedPath {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let open = &self.path.links[0];
+        write!(f, "{}, blocked by {} {} (coil {})", self.path, open.switch, open.contact,
+               if self.coils.is_empty() { "missing".to_string() } else { self.coils.iter().map(Handle::to_string).collect::<Vec<_>>().join(", ") })
+    }
+}
+
+/// Most candidate paths `Circuit::why_not` reports
+pub const WHY_NOT_LIMIT: usize = 16;
+
+impl Circuit {
+
+    /// How power reached `handle` during the last tick, or `None` if it was not powered
+    ///
+    /// The path is a shortest one through the contacts as they stood during that tick, starting
+    /// from `G` or one of the tick's sources, e.g. `G -[ab_0 NO]- ab_0.no -[ba_-3 NC]- Aa_0`.
+    pub fn explain(&self, handle: &Handle) -> Option<ConductionPath> {
+        self.try_explain(handle).unwrap_or_else(|err| panic!("{} to explain", err))
+    }
+
+    /// Like `explain`, but reports a missing label instead of panicking
+    pub fn try_explain(&self, handle: &Handle) -> Result<Option<ConductionPath>, Z3muError> {
+        let target = self.node_of(handle).ok_or_else(|| unknown_label(handle))?;
+        Ok(self.explain_node(target))
+    }
+
+    fn explain_node(&self, target: NodeId) -> Option<ConductionPath> {
+        if !self.powered[target] {
+            return None;
+        }
+        let mut sources: HashSet<NodeId> = self.last_sources.iter().copied().collect();
+        sources.insert(self.labels[&handle!("G")]);
+        let hops = self.closed_path(&self.last_positions, target, &sources)?;
+
+        // hops lead from the target back to the source, so walk them the other way
+        let mut nodes: Vec<NodeId> = std::iter::once(target).chain(hops.iter().map(|&(_, _, node)| node)).collect();
+        nodes.reverse();
+        let links = hops
+            .iter()
+            .rev()
+            .zip(&nodes[1..])
+            .map(|(&(switch, closed_when, _), &node)| Link {
+                switch: self.switches[switch].name.clone(),
+                contact: ContactKind::from_closed_when(closed_when),
+                node: self.node_name(node),
+            })
+            .collect();
+        Some(ConductionPath { source: self.node_name(nodes[0]), links })
+    }
+
+    /// Why `handle` was not powered during the last tick, as the shortest paths to it from the
+    /// powered nodes, or nothing if it was powered
+    ///
+    /// Each path begins at the open contact on the edge of a powered region, along with the coils
+    /// operating that contact's switch. Contacts further along may be open as well. At most
+    /// `WHY_NOT_LIMIT` paths are returned, shortest first.
+    pub fn why_not(&self, handle: &Handle) -> Vec<BlockedPath> {
+        self.try_why_not(handle).unwrap_or_else(|err| panic!("{} to diagnose", err))
+    }
+
+    /// Like `why_not`, but reports a missing label instead of panicking
+    pub fn try_why_not(&self, handle: &Handle) -> Result<Vec<BlockedPath>, Z3muError> {
+        let target = self.node_of(handle).ok_or_else(|| unknown_label(handle))?;
+        Ok(self.why_not_node(target))
+    }
+
+    fn why_not_node(&self, target: NodeId) -> Vec<BlockedPath> {
+        let mut ret = Vec::new();
+        if self.powered[target] {
+            return ret;
+        }
+        let mut towards_target: HashMap<NodeId, (NodeId, SwitchId, bool)> = HashMap::new();
+        let mut queue = VecDeque::from([target]);
+        let mut visited = HashSet::from([target]);
+        while let Some(node) = queue.pop_front() {
+            for contact in self.contacts.row(node) {
+                if self.powered[contact.other] {
+                    if self.last_positions[contact.switch] == contact.closed_when {
+                        continue;
+                    }
+                    ret.push(self.blocked_path(contact.other, (node, contact.switch, contact.closed_when), &towards_target));
+                    if ret.len() == WHY_NOT_LIMIT {
+                        return ret;
+                    }
+                } else if visited.insert(contact.other) {
+                    towards_target.insert(contact.other, (node, contact.switch, contact.closed_when));
+                    queue.push_back(contact.other);
+                }
+            }
+        }
+        ret
+    }
+
+    /// Follows `towards_target` from the far side of an open contact leaving `source`
+    fn blocked_path(&self, source: NodeId, open: (NodeId, SwitchId, bool), towards_target: &HashMap<NodeId, (NodeId, SwitchId, bool)>) -> BlockedPath {
+        let link = |(node, switch, closed_when): (NodeId, SwitchId, bool)| Link {
+            switch: self.switches[switch].name.clone(),
+            contact: ContactKind::from_closed_when(closed_when),
+            node: self.node_name(node),
+        };
+        let mut links = vec![link(open)];
+        let mut curr = open.0;
+        while let Some(&next) = towards_target.get(&curr) {
+            links.push(link(next));
+            curr = next.0;
+        }
+        let relay = &self.switches[open.1].name;
+        let coils = self.coil_nodes
+            .iter()
+            .filter(|(coil, _)| CircuitBuilder::coil_to_switch_name(coil) == *relay)
+            .map(|(coil, _)| coil.clone())
+            .collect();
+        BlockedPath { path: ConductionPath { source: self.node_name(source), links }, coils }
+    }
+
+    /// Shortest chain of contacts closed under `positions` from `from` to any node of `to`, as
+    /// (switch, closed_when, node reached) hops starting at `from`
+    pub(super) fn closed_path(&self, positions: &BitSet, from: NodeId, to: &HashSet<NodeId>) -> Option<Vec<(SwitchId, bool, NodeId)>> {
+        let mut parents: HashMap<NodeId, (NodeId, SwitchId, bool)> = HashMap::new();
+        let mut queue = VecDeque::from([from]);
+        let mut visited = HashSet::from([from]);
+        while let Some(node) = queue.pop_front() {
+            if to.contains(&node) {
+                let mut hops = Vec::new();
+                let mut curr = node;
+                while let Some(&(parent, switch, closed_when)) = parents.get(&curr) {
+                    hops.push((switch, closed_when, curr));
+                    curr = parent;
+                }
+                hops.reverse();
+                return Some(hops);
+            }
+            for contact in self.contacts.row(node) {
+                if positions[contact.switch] == contact.closed_when && visited.insert(contact.other) {
+                    parents.insert(contact.other, (node, contact.switch, contact.closed_when));
+                    queue.push_back(contact.other);
+                }
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn explains_path_through_contacts() {
+        let mut c = CircuitBuilder::new()
+            .add_subcircuit(|mut scb| {
+                let (g, input) = (scb.label("G"), scb.label("Aa"));
+                scb.coil(handle!("Ab", 0), input);
+                scb.coil(handle!("Ab", 1), input);
+                let (_, mid, _) = scb.add_switch(handle!("ab", 0), (g, None, None));
+                let (out, echo) = (scb.label("Out"), scb.label("Echo"));
+                scb.add_switch(handle!("ba", -3), (mid, None, out));
+                scb.add_switch(handle!("ab", 1), (input, echo, None));
+            })
+            .finalize();
+        assert_eq!(c.explain(&handle!("Out")), None);
+
+        c.set(&handle!("Aa"));
+        c.step();
+        assert_eq!(c.explain(&handle!("Out")), None);
+        assert_eq!(c.explain(&handle!("Aa")).unwrap().to_string(), "Aa");
+
+        c.set(&handle!("Aa"));
+        c.step();
+        let path = c.explain(&handle!("Out")).unwrap();
+        assert_eq!(path.to_string(), "G -[ab_0 NO]- ab_0.no -[ba_-3 NC]- Out");
+        assert_eq!(path.links[1], Link { switch: handle!("ba", -3), contact: ContactKind::Nc, node: "Out".into() });
+        assert_eq!(c.explain(&handle!("Echo")).unwrap().to_string(), "Aa -[ab_1 NO]- Echo");
+    }
+
+    #[test]
+    fn why_not_names_open_contacts() {
+        let mut c = CircuitBuilder::new()
+            .add_subcircuit(|mut scb| {
+                let (g, input, enable) = (scb.label("G"), scb.label("In"), scb.label("En"));
+                scb.coil(handle!("Ab", 0), input);
+                scb.coil(handle!("Ba", -3), enable);
+                let (_, mid, _) = scb.add_switch(handle!("ab", 0), (g, None, None));
+                let out = scb.label("Out");
+                scb.add_switch(handle!("ca", 0), (mid, out, None));
+                scb.add_switch(handle!("ba", -3), (g, None, out));
+            })
+            .finalize();
+        c.hold(&handle!("En"));
+        c.step();
+        assert!(c.why_not(&handle!("Out")).is_empty());
+        c.step();
+        let reasons: Vec<String> = c.why_not(&handle!("Out")).iter().map(BlockedPath::to_string).collect();
+        assert_eq!(reasons, vec![
+            "G -[ba_-3 NC]- Out, blocked by ba_-3 NC (coil Ba_-3)",
+            "G -[ab_0 NO]- ab_0.no -[ca_0 NO]- Out, blocked by ab_0 NO (coil Ab_0)",
+        ]);
+    }
+
+    #[test]
+    fn why_not_names_coils_by_their_handles() {
+        let mut c = CircuitBuilder::new()
+            .add_subcircuit(|mut scb| {
+                let (g, drive, out) = (scb.label("G"), scb.label("Drive"), scb.label("Out"));
+                scb.coil(handle!("Ab", 0), drive);
+                scb.add_switch(handle!("ab", 0), (g, out, None));
+            })
+            .finalize();
+        c.step();
+        let reasons = c.why_not(&handle!("Out"));
+        assert_eq!(reasons[0].coils, vec![handle!("Ab", 0)]);
+        assert_eq!(reasons[0].to_string(), "G -[ab_0 NO]- Out, blocked by ab_0 NO (coil Ab_0)");
+    }
+}
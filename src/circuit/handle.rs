@@ -1,3 +1,7 @@
+use std::str::FromStr;
+
+use super::Z3muError;
+
 #[derive(PartialEq, Eq, Hash, Debug, Clone)]
 pub struct Handle {
     pub name: String,
@@ -12,6 +16,30 @@ impl Handle {
         assert!(!name.contains('^'));
         Handle { name, index, sup }
     }
+
+    /// Like `new`, but reports a name containing `_` or `^` instead of panicking
+    pub fn try_new<T: Into<String>>(name: T, index: Option<i8>, sup: Option<u8>) -> Result<Self, Z3muError> {
+        let name = name.into();
+        if name.contains('_') || name.contains('^') {
+            return Err(Z3muError::InvalidHandle { text: name });
+        }
+        Ok(Handle { name, index, sup })
+    }
+
+    fn parse(text: &str) -> Option<Handle> {
+        let (rest, sup) = match text.split_once('^') {
+            Some((rest, sup)) => (rest, Some(sup.parse().ok()?)),
+            None => (text, None),
+        };
+        let (name, index) = match rest.split_once('_') {
+            Some((name, index)) => (name, Some(index.parse().ok()?)),
+            None => (rest, None),
+        };
+        if name.is_empty() {
+            return None;
+        }
+        Some(Handle { name: name.into(), index, sup })
+    }
 }
 
 impl std::fmt::Display for Handle {
@@ -27,24 +55,18 @@ impl std::fmt::Display for Handle {
     }
 }
 
+/// Parses the text form of a handle, e.g. `Ba_-3^1`, without panicking on malformed input
+impl FromStr for Handle {
+    type Err = Z3muError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Handle::parse(s).ok_or_else(|| Z3muError::InvalidHandle { text: s.into() })
+    }
+}
+
 impl From<&str> for Handle {
     fn from(s: &str) -> Handle {
-        let mut rem: &str = s;
-        let sup = rem.find('^').map(|i| {
-            let sup = rem[i+1..].parse().unwrap();
-            rem = &rem[..i];
-            sup
-        });
-        let index = rem.find('_').map(|i| {
-            let index = rem[i+1..].parse().expect(&format!("Failed to parse handle {}", s));
-            rem = &rem[..i];
-            index
-        });
-        Handle {
-            name: rem.into(),
-            index,
-            sup,
-        }
+        s.parse().unwrap_or_else(|err| panic!("Failed to parse handle: {}", err))
     }
 }
 
@@ -55,6 +77,7 @@ impl From<String> for Handle {
 }
 
 
+#[macro_export]
 macro_rules! handle {
     ( $name:expr ) => {
         Handle::new($name, None, None)
@@ -67,6 +90,7 @@ macro_rules! handle {
     };
 }
 
+#[derive(PartialEq, Eq, Hash, Debug, Clone)]
 pub struct Bus {
     pub name: String,
     pub sup: Option<u8>,
@@ -80,6 +104,15 @@ impl Bus {
         Bus { name, sup }
     }
 
+    /// Like `new`, but reports a name containing `_` or `^` instead of panicking
+    pub fn try_new<T: Into<String>>(name: T, sup: Option<u8>) -> Result<Self, Z3muError> {
+        let name = name.into();
+        if name.contains('_') || name.contains('^') {
+            return Err(Z3muError::InvalidHandle { text: name });
+        }
+        Ok(Bus { name, sup })
+    }
+
     pub fn index(&self, index: i8) -> Handle {
         Handle::new(self.name.clone(), Some(index), self.sup)
     }
@@ -95,6 +128,7 @@ impl std::fmt::Display for Bus {
     }
 }
 
+#[macro_export]
 macro_rules! bus {
     ( $name:expr ) => {
         Bus::new($name, None)
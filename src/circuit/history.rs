@@ -0,0 +1,376 @@
+use std::collections::VecDeque;
+use std::mem::size_of;
+use std::ops::RangeInclusive;
+
+use super::{Circuit, NodeId, SwitchId};
+use super::topology::BitSet;
+
+/// The requested step has been dropped from the history or was never reached
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OutOfHistory {
+    pub step: u64,
+    pub available: Option<RangeInclusive<u64>>, // steps that can be returned to, if history is on
+}
+
+impl std::fmt::Display for OutOfHistory {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.available {
+            Some(available) => write!(f, "step {} is not in the history (steps {} to {} are)", self.step, available.start(), available.end()),
+            None => write!(f, "step {} is not in the history (history is off)", self.step),
+        }
+    }
+}
+
+impl std::error::Error for OutOfHistory {}
+
+type ClockPosition = (u64, u8); // (cycle, phase) of the phase clock
+
+/// What the last tick of a step left behind for `explain` and `why_not`
+struct LastTick {
+    moved: Vec<SwitchId>, // switches that moved during the tick
+    sources: Vec<NodeId>,
+}
+
+impl LastTick {
+    fn of(c: &Circuit) -> Self {
+        LastTick {
+            moved: c.last_positions.differences(&c.switch_positions).collect(),
+            sources: c.last_sources.clone(),
+        }
+    }
+
+    fn bytes(&self) -> usize {
+        self.moved.capacity() * size_of::<SwitchId>()
+            + self.sources.capacity() * size_of::<NodeId>()
+    }
+}
+
+/// Changes made by one step; switch moves and powered flips undo themselves when replayed
+struct Delta {
+    switches: Vec<SwitchId>, // switches that moved
+    held_for: Vec<(SwitchId, u32, u32)>, // armatures whose count changed, with the before and after counts
+    powered: Vec<NodeId>, // nodes whose powered state flipped
+    ticks: (u64, u64),
+    clock: (Option<ClockPosition>, Option<ClockPosition>),
+    last_tick: LastTick, // the last tick of the step the delta leads to
+}
+
+impl Delta {
+    fn bytes(&self) -> usize {
+        size_of::<Delta>()
+            + self.switches.capacity() * size_of::<SwitchId>()
+            + self.held_for.capacity() * size_of::<(SwitchId, u32, u32)>()
+            + self.powered.capacity() * size_of::<NodeId>()
+            + self.last_tick.bytes()
+    }
+}
+
+/// Per-step deltas behind and ahead of the current step, within a memory budget
+pub(super) struct History {
+    max_bytes: usize,
+    bytes: usize, // estimated size of deltas
+    deltas: VecDeque<Delta>, // deltas[i] leads from step first + i to first + i + 1
+    first: u64, // earliest step that can be returned to
+    cursor: usize, // deltas behind the current step
+    first_last_tick: LastTick, // the last tick of step first
+
+    // state at the current step
+    positions: BitSet,
+    held_for: Vec<u32>,
+    powered: Vec<bool>,
+    ticks: u64,
+    clock: Option<ClockPosition>,
+}
+
+impl History {
+    fn new(max_bytes: usize, c: &Circuit) -> Self {
+        History {
+            max_bytes,
+            bytes: 0,
+            deltas: VecDeque::new(),
+            first: c.steps,
+            cursor: 0,
+            first_last_tick: LastTick::of(c),
+            positions: c.switch_positions.clone(),
+            held_for: (0..c.switches.len()).map(|switch| c.armatures.held_for(switch)).collect(),
+            powered: c.powered.clone(),
+            ticks: c.ticks,
+            clock: c.clock_position(),
+        }
+    }
+
+    fn available(&self) -> RangeInclusive<u64> {
+        self.first..=self.first + self.deltas.len() as u64
+    }
+
+    /// Records how the circuit got from the current step to its state now, forgetting any steps
+    /// that had been stepped back over and then the oldest steps until the deltas fit the budget
+    fn record(&mut self, c: &Circuit) {
+        for dropped in self.deltas.drain(self.cursor..) {
+            self.bytes -= dropped.bytes();
+        }
+
+        let switches: Vec<SwitchId> = self.positions.differences(&c.switch_positions).collect();
+        let held_for: Vec<(SwitchId, u32, u32)> = self.held_for
+            .iter_mut()
+            .enumerate()
+            .filter_map(|(switch, before)| {
+                let after = c.armatures.held_for(switch);
+                (*before != after).then(|| (switch, std::mem::replace(before, after), after))
+            })
+            .collect();
+        let powered: Vec<NodeId> = self.powered
+            .iter_mut()
+            .zip(&c.powered)
+            .enumerate()
+            .filter(|(_, (before, after))| *before != *after)
+            .map(|(node, (before, after))| {
+                *before = *after;
+                node
+            })
+            .collect();
+        self.positions.clone_from(&c.switch_positions);
+        let delta = Delta {
+            switches,
+            held_for,
+            powered,
+            ticks: (self.ticks, c.ticks),
+            clock: (self.clock, c.clock_position()),
+            last_tick: LastTick::of(c),
+        };
+        self.ticks = c.ticks;
+        self.clock = c.clock_position();
+
+        self.bytes += delta.bytes();
+        self.deltas.push_back(delta);
+        self.cursor += 1;
+        while self.bytes > self.max_bytes {
+            let Some(dropped) = self.deltas.pop_front() else {
+                break;
+            };
+            self.bytes -= dropped.bytes();
+            self.first_last_tick = dropped.last_tick;
+            self.first += 1;
+            self.cursor -= 1;
+        }
+    }
+
+    /// Applies `deltas[index]`, forwards or backwards, to the state at the current step
+    fn apply(&mut self, index: usize, forwards: bool) {
+        let delta = &self.deltas[index];
+        for &switch in &delta.switches {
+            self.positions.set(switch, !self.positions[switch]);
+        }
+        for &(switch, before, after) in &delta.held_for {
+            self.held_for[switch] = if forwards { after } else { before };
+        }
+        for &node in &delta.powered {
+            self.powered[node] = !self.powered[node];
+        }
+        (self.ticks, self.clock) = if forwards { (delta.ticks.1, delta.clock.1) } else { (delta.ticks.0, delta.clock.0) };
+    }
+
+    fn last_tick(&self) -> &LastTick {
+        match self.cursor {
+            0 => &self.first_last_tick,
+            cursor => &self.deltas[cursor - 1].last_tick,
+        }
+    }
+
+    fn seek(&mut self, step: u64) {
+        let target = (step - self.first) as usize;
+        while self.cursor > target {
+            self.cursor -= 1;
+            self.apply(self.cursor, false);
+        }
+        while self.cursor < target {
+            self.apply(self.cursor, true);
+            self.cursor += 1;
+        }
+    }
+}
+
+impl Circuit {
+
+    /// Starts recording a delta for every step so that the simulation can be rewound, keeping
+    /// roughly `max_bytes` of deltas and dropping the oldest steps beyond that
+    ///
+    /// Held inputs are not part of the history: they stay as they are when stepping back.
+    pub fn record_history(&mut self, max_bytes: usize) {
+        self.history = Some(History::new(max_bytes, self));
+    }
+
+    pub fn stop_recording_history(&mut self) {
+        self.history = None;
+    }
+
+    /// Steps the circuit can currently be moved to with `goto_step`, if history is on
+    pub fn history_range(&self) -> Option<RangeInclusive<u64>> {
+        self.history.as_ref().map(History::available)
+    }
+
+    /// Number of calls to `step` and `settle` since the circuit was finalized, less any stepped
+    /// back over
+    pub fn steps(&self) -> u64 {
+        self.steps
+    }
+
+    /// Undoes the last step, returning false if it is not in the history
+    pub fn step_back(&mut self) -> bool {
+        self.steps > 0 && self.goto_step(self.steps - 1).is_ok()
+    }
+
+    /// Moves to the state after `step` steps, either back in the history or forward again over
+    /// steps that were undone; stepping normally from there discards the undone steps
+    pub fn goto_step(&mut self, step: u64) -> Result<(), OutOfHistory> {
+        let Some(mut history) = self.history.take() else {
+            return Err(OutOfHistory { step, available: None });
+        };
+        let available = history.available();
+        if !available.contains(&step) {
+            self.history = Some(history);
+            return Err(OutOfHistory { step, available: Some(available) });
+        }
+        history.seek(step);
+
+        self.switch_positions.clone_from(&history.positions);
+        let last_tick = history.last_tick();
+        self.last_positions.clone_from(&self.switch_positions);
+        for &switch in &last_tick.moved {
+            self.last_positions.set(switch, !self.last_positions[switch]);
+        }
+        self.last_sources.clone_from(&last_tick.sources);
+        for (switch, &held_for) in history.held_for.iter().enumerate() {
+            self.armatures.set_held_for(switch, held_for);
+        }
+        self.powered.clone_from(&history.powered);
+        for (node_id, b) in &mut self.traces {
+            *b = self.powered[*node_id];
+        }
+        self.ticks = history.ticks;
+        if let (Some(clock), Some((cycle, phase))) = (&mut self.clock, history.clock) {
+            clock.jump_to(cycle, phase);
+        }
+        self.steps = step;
+        self.sources.clear();
+        self.held_changed = true;
+        self.event.invalidate();
+        if let Some(detector) = &mut self.cycles {
+            detector.restart(&self.switch_positions);
+        }
+        self.resync_observers();
+        self.history = Some(history);
+        Ok(())
+    }
+
+    /// Called at the end of every step
+    pub(super) fn record_step(&mut self) {
+        if let Some(mut history) = self.history.take() {
+            history.record(self);
+            self.history = Some(history);
+        }
+    }
+
+    /// Restarts the history from the current state after a jump that it did not record
+    pub(super) fn reset_history(&mut self) {
+        if let Some(history) = &self.history {
+            self.history = Some(History::new(history.max_bytes, self));
+        }
+    }
+
+    fn clock_position(&self) -> Option<ClockPosition> {
+        self.clock.as_ref().map(|clock| (clock.cycle(), clock.phase()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::circuit::{Bus, CircuitBuilder, Handle, Timing};
+    use crate::common::gate;
+
+    fn gate_fixture() -> Circuit {
+        CircuitBuilder::new()
+            .add_subcircuit(gate(bus!("Ab"), handle!("Ga"), bus!("Aa"), 0..=7))
+            .add_subcircuit(|mut scb| {
+                for i in 0..=7 {
+                    let ab = scb.coil_with_timing(handle!("Ab", i), None, Timing::new(1, 2));
+                    let aa = scb.label(handle!("Aa", i));
+                    scb.trace_all([ab, aa]);
+                }
+            })
+            .finalize()
+    }
+
+    #[test]
+    fn rewind_and_redo() {
+        let values = [5, -3, 100, 0, -128, 77];
+        let mut c = gate_fixture();
+        c.record_history(1 << 20);
+        let mut seen = Vec::new();
+        for value in values {
+            c.set_bus(&bus!("Ab"), value);
+            c.set(&handle!("Ga"));
+            c.step();
+            c.set(&handle!("S", 5));
+            c.step();
+            seen.push((c.steps(), c.ticks(), c.switch_positions.clone(), c.inspect_bus(&bus!("Aa"))));
+        }
+        assert_eq!(c.history_range(), Some(0..=12));
+
+        for (steps, ticks, positions, value) in seen.iter().rev() {
+            c.goto_step(*steps).unwrap();
+            assert_eq!((c.steps(), c.ticks()), (*steps, *ticks));
+            assert_eq!(&c.switch_positions, positions);
+            assert_eq!(c.inspect_bus(&bus!("Aa")), *value);
+        }
+        assert!(c.step_back());
+        assert_eq!(c.steps(), 1);
+        c.goto_step(12).unwrap();
+        assert_eq!(c.inspect_bus(&bus!("Aa")), 77);
+
+        // stepping again from the past replaces the steps that followed
+        c.goto_step(2).unwrap();
+        c.step();
+        assert_eq!(c.history_range(), Some(0..=3));
+        assert!(matches!(c.goto_step(4), Err(OutOfHistory { step: 4, .. })));
+    }
+
+    #[test]
+    fn explain_after_rewind() {
+        let mut c = gate_fixture();
+        c.record_history(1 << 20);
+        let mut seen = Vec::new();
+        for value in [5, 2, 7] {
+            c.set_bus(&bus!("Ab"), value);
+            c.set(&handle!("Ga"));
+            c.step();
+            c.set(&handle!("S", 5));
+            c.step();
+            seen.push((c.steps(), c.explain(&handle!("Aa", 0)), c.why_not(&handle!("Aa", 1))));
+        }
+        assert!(seen.iter().any(|(_, path, _)| path.is_some()));
+
+        for (steps, path, blocked) in seen.iter().rev() {
+            c.goto_step(*steps).unwrap();
+            assert_eq!(&c.explain(&handle!("Aa", 0)), path);
+            assert_eq!(&c.why_not(&handle!("Aa", 1)), blocked);
+        }
+    }
+
+    #[test]
+    fn memory_cap_drops_oldest_steps() {
+        let mut c = gate_fixture();
+        c.record_history(4 * size_of::<Delta>());
+        for value in 0..20 {
+            c.set_bus(&bus!("Ab"), value);
+            c.step();
+        }
+        let range = c.history_range().unwrap();
+        assert_eq!(*range.end(), 20);
+        assert!(*range.start() > 0);
+        assert!(c.goto_step(0).is_err());
+        while c.step_back() {}
+        assert_eq!(c.steps(), *range.start());
+    }
+}
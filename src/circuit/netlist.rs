@@ -0,0 +1,393 @@
+use std::path::Path;
+
+use super::{CircuitBuilder, Handle, NodeId, SubcircuitBuilder, Z3muError};
+use super::error::FileError;
+
+/// Problems loading a netlist, located by 1-based line and column
+#[derive(Debug)]
+pub enum NetlistError {
+    File(FileError),
+    Build { line: usize, column: usize, error: Z3muError }, // the builder rejected a statement
+}
+
+impl std::fmt::Display for NetlistError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            NetlistError::File(err) => write!(f, "{}", err),
+            NetlistError::Build { line, column, error } => write!(f, "line {}, column {}: {}", line, column, error),
+        }
+    }
+}
+
+impl std::error::Error for NetlistError {}
+
+impl From<FileError> for NetlistError {
+    fn from(err: FileError) -> Self {
+        NetlistError::File(err)
+    }
+}
+
+struct Token<'a> {
+    text: &'a str,
+    column: usize,
+}
+
+type Nodes = Vec<Option<Handle>>; // None for a fresh node
+
+struct Statement {
+    line: usize,
+    column: usize,
+    kind: Kind,
+}
+
+enum Kind {
+    Global(Vec<String>),
+    Coil { coils: Vec<Handle>, column: usize, on: Nodes }, // column of the coil handle
+    Label(Vec<Handle>),
+    Trace(Vec<Handle>),
+    Contact { relays: Vec<Handle>, terminals: [Nodes; 3] }, // pole, NO, NC
+    Block { name: String, column: usize, body: Vec<Statement> }, // column of the name
+}
+
+fn tokenize(line: &str) -> Vec<Token<'_>> {
+    let line = line.split_once('#').map_or(line, |(code, _)| code);
+    let mut tokens = Vec::new();
+    let mut start = None;
+    for (column, (i, c)) in line.char_indices().chain([(line.len(), ' ')]).enumerate() {
+        match (start, c.is_whitespace()) {
+            (None, false) => start = Some((i, column + 1)),
+            (Some((begin, column)), true) => {
+                tokens.push(Token { text: &line[begin..i], column });
+                start = None;
+            }
+            _ => {}
+        }
+    }
+    tokens
+}
+
+struct Parser {
+    line: usize,
+}
+
+impl Parser {
+    fn error<T>(&self, column: usize, message: String) -> Result<T, NetlistError> {
+        Err(NetlistError::File(FileError::Parse { line: self.line, column: Some(column), message }))
+    }
+
+    /// Handles named by a token, one per index of its range if it has one
+    fn handles(&self, token: &Token) -> Result<Vec<Handle>, NetlistError> {
+        let texts = match token.text.split_once('{') {
+            None => vec![token.text.to_string()],
+            Some((prefix, rest)) => {
+                let range_column = token.column + prefix.chars().count();
+                let Some((range, suffix)) = rest.split_once('}') else {
+                    return self.error(range_column, "unclosed range".into());
+                };
+                let bounds = range.split_once("..").and_then(|(from, to)| Some((from.parse::<i8>().ok()?, to.parse::<i8>().ok()?)));
+                let Some((from, to)) = bounds else {
+                    return self.error(range_column, format!("expected a range such as {{-16..1}}, found {{{}}}", range));
+                };
+                let indices: Vec<i8> = if from <= to { (from..=to).collect() } else { (to..=from).rev().collect() };
+                indices.into_iter().map(|index| format!("{}{}{}", prefix, index, suffix)).collect()
+            }
+        };
+        texts
+            .into_iter()
+            .map(|text| match text.parse() {
+                Ok(handle) if !text.contains(['{', '}', '=']) => Ok(handle),
+                _ => self.error(token.column, format!("\"{}\" is not a valid handle", text)),
+            })
+            .collect()
+    }
+
+    fn nodes(&self, token: &Token) -> Result<Nodes, NetlistError> {
+        if token.text == "-" {
+            return Ok(vec![None]);
+        }
+        Ok(self.handles(token)?.into_iter().map(Some).collect())
+    }
+
+    fn handle_list(&self, tokens: &[Token]) -> Result<Vec<Handle>, NetlistError> {
+        let mut handles = Vec::new();
+        for token in tokens {
+            handles.extend(self.handles(token)?);
+        }
+        Ok(handles)
+    }
+
+    /// Nodes given for each of `n` expanded statements, repeating a single one
+    fn broadcast(&self, n: usize, nodes: Nodes, column: usize) -> Result<Nodes, NetlistError> {
+        match nodes.len() {
+            1 => Ok(vec![nodes[0].clone(); n]),
+            len if len == n => Ok(nodes),
+            len => self.error(column, format!("expected 1 or {} nodes, found {}", n, len)),
+        }
+    }
+
+    fn statement(&self, tokens: &[Token]) -> Result<Kind, NetlistError> {
+        let keyword = &tokens[0];
+        let missing = |what: &str| self.error(keyword.column + keyword.text.chars().count(), format!("expected {} after {}", what, keyword.text));
+        match keyword.text {
+            "global" if tokens.len() > 1 => Ok(Kind::Global(tokens[1..].iter().map(|token| token.text.to_string()).collect())),
+            "label" if tokens.len() > 1 => Ok(Kind::Label(self.handle_list(&tokens[1..])?)),
+            "trace" if tokens.len() > 1 => Ok(Kind::Trace(self.handle_list(&tokens[1..])?)),
+            "global" | "label" | "trace" => missing("a handle"),
+            "coil" => {
+                let Some(coil) = tokens.get(1) else {
+                    return missing("a coil");
+                };
+                let coils = self.handles(coil)?;
+                let on = match &tokens[2..] {
+                    [] => vec![None],
+                    [on, node] if on.text == "on" => self.broadcast(coils.len(), self.nodes(node)?, node.column)?,
+                    [other, ..] => return self.error(other.column, format!("expected `on <node>`, found \"{}\"", other.text)),
+                };
+                Ok(Kind::Coil { on: self.broadcast(coils.len(), on, coil.column)?, coils, column: coil.column })
+            }
+            "contact" => {
+                let Some(relay) = tokens.get(1) else {
+                    return missing("a relay");
+                };
+                let relays = self.handles(relay)?;
+                let mut terminals: [Option<Nodes>; 3] = Default::default();
+                for token in &tokens[2..] {
+                    let slot = match token.text.split_once('=') {
+                        Some(("pole", _)) => 0,
+                        Some(("no", _)) => 1,
+                        Some(("nc", _)) => 2,
+                        _ => return self.error(token.column, format!("expected pole=, no= or nc=, found \"{}\"", token.text)),
+                    };
+                    let (key, value) = token.text.split_once('=').unwrap();
+                    if terminals[slot].is_some() {
+                        return self.error(token.column, format!("{} is given twice", key));
+                    }
+                    let value = Token { text: value, column: token.column + key.len() + 1 };
+                    terminals[slot] = Some(self.broadcast(relays.len(), self.nodes(&value)?, value.column)?);
+                }
+                let terminals = terminals.map(|nodes| nodes.unwrap_or_else(|| vec![None; relays.len()]));
+                Ok(Kind::Contact { relays, terminals })
+            }
+            other => self.error(keyword.column, format!("unknown statement \"{}\"", other)),
+        }
+    }
+}
+
+fn parse(text: &str) -> Result<Vec<Statement>, NetlistError> {
+    let mut blocks: Vec<(Statement, Vec<Statement>)> = Vec::new(); // open blocks, innermost last
+    let mut root = Vec::new();
+    for (i, line) in text.lines().enumerate() {
+        let parser = Parser { line: i + 1 };
+        let tokens = tokenize(line);
+        let Some(first) = tokens.first() else {
+            continue;
+        };
+        let statement = match (first.text, &tokens[1..]) {
+            ("subcircuit", [name, open]) if open.text == "{" => {
+                if name.text.is_empty() || name.text.contains(['_', '^', '/', '{', '}']) {
+                    return parser.error(name.column, format!("\"{}\" is not a valid subcircuit name", name.text));
+                }
+                let siblings = blocks.last().map_or(&root, |(_, body)| body);
+                let defined = siblings.iter().find(|sibling| matches!(&sibling.kind, Kind::Block { name: other, .. } if other == name.text));
+                if let Some(sibling) = defined {
+                    return parser.error(name.column, format!("subcircuit {} is already defined on line {}", name.text, sibling.line));
+                }
+                let block = Kind::Block { name: name.text.to_string(), column: name.column, body: Vec::new() };
+                blocks.push((Statement { line: parser.line, column: first.column, kind: block }, Vec::new()));
+                continue;
+            }
+            ("subcircuit", _) => return parser.error(first.column, "expected `subcircuit <name> {`".into()),
+            ("}", []) => {
+                let Some((mut block, statements)) = blocks.pop() else {
+                    return parser.error(first.column, "unmatched }".into());
+                };
+                if let Kind::Block { body, .. } = &mut block.kind {
+                    *body = statements;
+                }
+                block
+            }
+            _ => Statement { line: parser.line, column: first.column, kind: parser.statement(&tokens)? },
+        };
+        match blocks.last_mut() {
+            Some((_, body)) => body.push(statement),
+            None => root.push(statement),
+        }
+    }
+    if let Some((block, _)) = blocks.pop() {
+        if let Kind::Block { name, .. } = block.kind {
+            let message = format!("subcircuit {} is never closed", name);
+            return Err(NetlistError::File(FileError::Parse { line: block.line, column: Some(block.column), message }));
+        }
+    }
+    Ok(root)
+}
+
+/// Adds the top-level statements gathered so far as a subcircuit of global labels
+fn add_global(cb: CircuitBuilder, statements: &mut Vec<Statement>, error: &mut Option<NetlistError>) -> CircuitBuilder {
+    if statements.is_empty() {
+        return cb;
+    }
+    let statements = std::mem::take(statements);
+    cb.add_named_subcircuit("netlist", |mut scb| build(&mut scb, &statements, error))
+}
+
+fn node(scb: &mut SubcircuitBuilder, node: &Option<Handle>) -> Option<NodeId> {
+    node.as_ref().map(|handle| scb.label(handle.clone()))
+}
+
+fn build(scb: &mut SubcircuitBuilder, statements: &[Statement], error: &mut Option<NetlistError>) {
+    for statement in statements {
+        if error.is_some() {
+            return;
+        }
+        match &statement.kind {
+            Kind::Global(patterns) => {
+                for pattern in patterns {
+                    scb.global(pattern);
+                }
+            }
+            Kind::Coil { coils, column, on } => {
+                for (coil, on) in coils.iter().zip(on) {
+                    let pos = node(scb, on);
+                    if let Err(err) = scb.try_coil(coil.clone(), pos) {
+                        *error = Some(NetlistError::Build { line: statement.line, column: *column, error: err });
+                        return;
+                    }
+                }
+            }
+            Kind::Label(labels) => {
+                for label in labels {
+                    scb.label(label.clone());
+                }
+            }
+            Kind::Trace(labels) => {
+                for label in labels {
+                    let node = scb.label(label.clone());
+                    scb.trace(node);
+                }
+            }
+            Kind::Contact { relays, terminals: [pole, no, nc] } => {
+                for (i, relay) in relays.iter().enumerate() {
+                    let terminals = (node(scb, &pole[i]), node(scb, &no[i]), node(scb, &nc[i]));
+                    scb.add_switch(relay.clone(), terminals);
+                }
+            }
+            Kind::Block { name, column, body } => {
+                if let Err(err) = scb.try_scoped(name, |mut scb| build(&mut scb, body, error)) {
+                    *error = Some(NetlistError::Build { line: statement.line, column: *column, error: err });
+                    return;
+                }
+            }
+        }
+    }
+}
+
+impl CircuitBuilder {
+
+    /// Adds the subcircuits described by a netlist
+    ///
+    /// A netlist is plain text with one statement per line and `#` starting a comment:
+    ///
+    /// ```text
+    /// subcircuit figure6 {
+    ///     global Ba_* Fp Fq
+    ///     coil Ba_{-16..1}
+    ///     coil Fp on in
+    ///     contact fq_{-16..1} pole=x_{-16..1} no=Ba_{-15..2} nc=Ba_{-16..1}
+    ///     contact fp pole=G no=- nc=in
+    ///     label spare
+    ///     trace x_{-16..1}
+    /// }
+    /// ```
+    ///
+    /// Nodes are named by labels, and `-` stands for a fresh node no one else can refer to; a contact
+    /// terminal left out is fresh as well. A `{a..b}` range, at most one per handle, expands a statement
+    /// into one per index, and handles given once are used for every index. Blocks nest and are scoped
+    /// as in `CircuitBuilder::add_scoped_subcircuit`, so blocks side by side need distinct names;
+    /// statements outside any block are global. Statements are built in the order they appear.
+    pub fn load_netlist(self, text: &str) -> Result<Self, NetlistError> {
+        let mut error = None;
+        let mut ret = self;
+        let mut global = Vec::new(); // top-level statements since the last block
+        for statement in parse(text)? {
+            let Kind::Block { name, column, body } = statement.kind else {
+                global.push(statement);
+                continue;
+            };
+            ret = add_global(ret, &mut global, &mut error);
+            ret = ret
+                .try_add_scoped_subcircuit(name, |mut scb| build(&mut scb, &body, &mut error))
+                .map_err(|err| NetlistError::Build { line: statement.line, column, error: err })?;
+        }
+        ret = add_global(ret, &mut global, &mut error);
+        error.map_or(Ok(ret), Err)
+    }
+
+    pub fn load_netlist_file(self, path: impl AsRef<Path>) -> Result<Self, NetlistError> {
+        let text = std::fs::read_to_string(path).map_err(FileError::Io)?;
+        self.load_netlist(&text)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::circuit::Bus;
+
+    const RELAYS: &str = "
+        # two relays picking up from their inputs
+        coil Ab_{1..0} on In_{1..0}
+        subcircuit relays {
+            global G Ab_* In_*
+            contact ab_{0..1} pole=G no=out_{0..1} nc=-
+            subcircuit spare {
+                contact ab_0
+            }
+            trace out_{0..1}
+        }
+    ";
+
+    #[test]
+    fn loads_netlist() {
+        let mut c = CircuitBuilder::new().load_netlist(RELAYS).unwrap().finalize();
+        assert_eq!(c.relay_contacts(&handle!("ab", 0)).len(), 2);
+        assert!(c.node_of(&handle!("relays/out", 1)).is_some());
+
+        c.set(&handle!("In", 1));
+        c.step();
+        c.step();
+        assert_eq!(c.traced(), vec![("relays/out_0".to_string(), false), ("relays/out_1".to_string(), true)]);
+        assert_eq!(c.inspect_bus(&bus!("relays/out")), -2);
+    }
+
+    #[test]
+    fn builds_in_file_order() {
+        let text = "
+            subcircuit early {
+                global Aa In
+                coil Aa on In
+            }
+            coil Aa
+        ";
+        let c = CircuitBuilder::new().load_netlist(text).unwrap().finalize();
+        assert_eq!(c.node_of(&handle!("Aa")), c.node_of(&handle!("In")));
+    }
+
+    #[test]
+    fn reports_error_locations() {
+        let error = |text: &str| CircuitBuilder::new().load_netlist(text).err().unwrap().to_string();
+        assert_eq!(error("coil Ab_{0..1"), "line 1, column 9: unclosed range");
+        assert_eq!(error("\n  coil Ab_{0..3} on In_{0..1}"), "line 2, column 21: expected 1 or 4 nodes, found 2");
+        assert_eq!(error("contact ab_0 pole=G no=a_x"), "line 1, column 24: \"a_x\" is not a valid handle");
+        assert_eq!(error("contact ab_0 middle=G"), "line 1, column 14: expected pole=, no= or nc=, found \"middle=G\"");
+        assert_eq!(error("relay ab_0"), "line 1, column 1: unknown statement \"relay\"");
+        assert_eq!(error("subcircuit a {\n  subcircuit b {\n  }"), "line 1, column 1: subcircuit a is never closed");
+        assert_eq!(error("label x\n}"), "line 2, column 1: unmatched }");
+        assert_eq!(error("label In\ncoil Ab_0\n  coil Ab_0 on In"),
+                   "line 3, column 8: coil Ab_0 in netlist is already on node 2 and cannot be moved");
+        assert_eq!(error("subcircuit a {\n}\nsubcircuit b {\n  subcircuit c {\n  }\n}\nsubcircuit a {\n}"),
+                   "line 7, column 12: subcircuit a is already defined on line 1");
+        assert_eq!(error("subcircuit a {\n  subcircuit c {\n  }\n  subcircuit c {\n  }\n}"),
+                   "line 4, column 14: subcircuit c is already defined on line 2");
+    }
+}
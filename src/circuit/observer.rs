@@ -0,0 +1,222 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use super::{Circuit, Handle, NodeId};
+
+/// Receives simulation events from a `Circuit` it has been added to
+///
+/// Every callback does nothing by default. Ticks and steps are numbered as `Circuit::ticks` and
+/// `Circuit::steps` will be once they are over, so the first step after finalize is step 1.
+pub trait SimObserver {
+    fn step_started(&mut self, _step: u64) {}
+
+    fn step_ended(&mut self, _step: u64) {}
+
+    /// `switch` moved at the end of `tick` and is now picked up if `position` is true
+    fn switch_toggled(&mut self, _tick: u64, _switch: &Handle, _position: bool) {}
+
+    fn coil_energized(&mut self, _tick: u64, _coil: &Handle) {}
+
+    fn coil_deenergized(&mut self, _tick: u64, _coil: &Handle) {}
+
+    /// A traced node, named by its first label, changed its powered state during `tick`
+    fn trace_changed(&mut self, _tick: u64, _node: &str, _powered: bool) {}
+
+    /// Nodes were traced or untraced; `traced` is the new trace set as given by `Circuit::traced`
+    fn traces_changed(&mut self, _traced: &[(String, bool)]) {}
+}
+
+/// Lets an observer be shared with the code that added it, to read its results back
+impl<T: SimObserver> SimObserver for Rc<RefCell<T>> {
+    fn step_started(&mut self, step: u64) {
+        self.borrow_mut().step_started(step)
+    }
+
+    fn step_ended(&mut self, step: u64) {
+        self.borrow_mut().step_ended(step)
+    }
+
+    fn switch_toggled(&mut self, tick: u64, switch: &Handle, position: bool) {
+        self.borrow_mut().switch_toggled(tick, switch, position)
+    }
+
+    fn coil_energized(&mut self, tick: u64, coil: &Handle) {
+        self.borrow_mut().coil_energized(tick, coil)
+    }
+
+    fn coil_deenergized(&mut self, tick: u64, coil: &Handle) {
+        self.borrow_mut().coil_deenergized(tick, coil)
+    }
+
+    fn trace_changed(&mut self, tick: u64, node: &str, powered: bool) {
+        self.borrow_mut().trace_changed(tick, node, powered)
+    }
+
+    fn traces_changed(&mut self, traced: &[(String, bool)]) {
+        self.borrow_mut().traces_changed(traced)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct ObserverId(usize);
+
+/// Registered observers and the coil and trace states they were last told about
+#[derive(Default)]
+pub(super) struct Observers {
+    list: Vec<(ObserverId, Box<dyn SimObserver>)>,
+    next_id: usize,
+    coils: Vec<bool>, // index into Circuit::coil_nodes -> energized after the last tick
+    traces: Vec<(NodeId, String, bool)>, // traced node, its name, powered after the last tick
+}
+
+fn each(list: &mut [(ObserverId, Box<dyn SimObserver>)], mut event: impl FnMut(&mut dyn SimObserver)) {
+    for (_, observer) in list {
+        event(observer.as_mut());
+    }
+}
+
+impl Observers {
+    pub(super) fn step_started(&mut self, step: u64) {
+        each(&mut self.list, |observer| observer.step_started(step));
+    }
+
+    pub(super) fn step_ended(&mut self, step: u64) {
+        each(&mut self.list, |observer| observer.step_ended(step));
+    }
+}
+
+impl Circuit {
+
+    pub fn add_observer(&mut self, observer: impl SimObserver + 'static) -> ObserverId {
+        let id = ObserverId(self.observers.next_id);
+        self.observers.next_id += 1;
+        if self.observers.list.is_empty() {
+            self.resync_observers();
+        }
+        self.observers.list.push((id, Box::new(observer)));
+        id
+    }
+
+    pub fn remove_observer(&mut self, id: ObserverId) -> Option<Box<dyn SimObserver>> {
+        let index = self.observers.list.iter().position(|(other, _)| *other == id)?;
+        Some(self.observers.list.remove(index).1)
+    }
+
+    /// Takes the current coil and trace states as the ones observers already know about
+    pub(super) fn resync_observers(&mut self) {
+        self.observers.coils = self.coil_nodes.iter().map(|(_, node_id)| self.powered[*node_id]).collect();
+        let mut traces: Vec<(NodeId, String, bool)> = self.traces
+            .iter()
+            .map(|(&node_id, &powered)| (node_id, self.node_name(node_id), powered))
+            .collect();
+        traces.sort_by(|a, b| a.1.cmp(&b.1));
+        self.observers.traces = traces;
+    }
+
+    pub(super) fn notify_traces_changed(&mut self) {
+        self.resync_observers();
+        if self.observers.list.is_empty() {
+            return;
+        }
+        let traced = self.traced();
+        each(&mut self.observers.list, |observer| observer.traces_changed(&traced));
+    }
+
+    /// Reports what changed during the tick that just ended
+    pub(super) fn notify_tick(&mut self) {
+        let observers = &mut self.observers;
+        if observers.list.is_empty() {
+            return;
+        }
+        let tick = self.ticks;
+        for ((coil, node_id), prev) in self.coil_nodes.iter().zip(&mut observers.coils) {
+            let energized = self.powered[*node_id];
+            if std::mem::replace(prev, energized) == energized {
+                continue;
+            }
+            if energized {
+                each(&mut observers.list, |observer| observer.coil_energized(tick, coil));
+            } else {
+                each(&mut observers.list, |observer| observer.coil_deenergized(tick, coil));
+            }
+        }
+        for (node_id, name, prev) in &mut observers.traces {
+            let powered = self.traces[node_id];
+            if std::mem::replace(prev, powered) != powered {
+                each(&mut observers.list, |observer| observer.trace_changed(tick, name, powered));
+            }
+        }
+        for switch in self.last_positions.differences(&self.switch_positions) {
+            let (name, position) = (&self.switches[switch].name, self.switch_positions[switch]);
+            each(&mut observers.list, |observer| observer.switch_toggled(tick, name, position));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::circuit::CircuitBuilder;
+
+    #[derive(Default)]
+    struct Recorder {
+        events: Vec<String>,
+    }
+
+    impl SimObserver for Recorder {
+        fn step_started(&mut self, step: u64) {
+            self.events.push(format!("start {}", step));
+        }
+
+        fn step_ended(&mut self, step: u64) {
+            self.events.push(format!("end {}", step));
+        }
+
+        fn switch_toggled(&mut self, tick: u64, switch: &Handle, position: bool) {
+            self.events.push(format!("{}: {} {}", tick, switch, if position { "up" } else { "down" }));
+        }
+
+        fn coil_energized(&mut self, tick: u64, coil: &Handle) {
+            self.events.push(format!("{}: {} on", tick, coil));
+        }
+
+        fn coil_deenergized(&mut self, tick: u64, coil: &Handle) {
+            self.events.push(format!("{}: {} off", tick, coil));
+        }
+
+        fn trace_changed(&mut self, tick: u64, node: &str, powered: bool) {
+            self.events.push(format!("{}: {}={}", tick, node, powered as u8));
+        }
+    }
+
+    #[test]
+    fn reports_events_in_order() {
+        let mut c = CircuitBuilder::new()
+            .add_subcircuit(|mut scb| {
+                let (g, input) = (scb.label("G"), scb.label("In"));
+                scb.coil(handle!("Ab", 0), input);
+                let out = scb.label("Out");
+                scb.add_switch(handle!("ab", 0), (g, out, None));
+                scb.trace(out);
+            })
+            .finalize();
+        let recorder = Rc::new(RefCell::new(Recorder::default()));
+        let id = c.add_observer(recorder.clone());
+
+        c.set(&handle!("In"));
+        c.step();
+        c.step();
+        c.step();
+        assert_eq!(recorder.borrow().events, vec![
+            "start 1", "1: Ab_0 on", "1: ab_0 up", "end 1",
+            "start 2", "2: Ab_0 off", "2: Out=1", "2: ab_0 down", "end 2",
+            "start 3", "3: Out=0", "end 3",
+        ]);
+
+        assert!(c.remove_observer(id).is_some());
+        assert!(c.remove_observer(id).is_none());
+        c.set(&handle!("In"));
+        c.step();
+        assert_eq!(recorder.borrow().events.len(), 12);
+    }
+}
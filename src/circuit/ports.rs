@@ -0,0 +1,253 @@
+use std::collections::{HashMap, HashSet};
+use std::ops::RangeInclusive;
+
+use super::{Bus, CircuitBuilder, Handle, NodeId, SubcircuitBuilder, Z3muError};
+use super::scope::Scope;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Direction {
+    Input,
+    Output,
+}
+
+/// Handle or bus through which an instance of a `Subcircuit` is wired to the rest of the circuit
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Port {
+    pub name: String, // handle name used inside the subcircuit, e.g. `From`
+    pub direction: Direction,
+    pub indices: Option<RangeInclusive<i8>>, // None for a single handle
+}
+
+impl Port {
+    pub fn input(name: &str) -> Port {
+        Port { name: name.into(), direction: Direction::Input, indices: None }
+    }
+
+    pub fn input_bus(name: &str, indices: RangeInclusive<i8>) -> Port {
+        Port { name: name.into(), direction: Direction::Input, indices: Some(indices) }
+    }
+
+    pub fn output(name: &str) -> Port {
+        Port { name: name.into(), direction: Direction::Output, indices: None }
+    }
+
+    pub fn output_bus(name: &str, indices: RangeInclusive<i8>) -> Port {
+        Port { name: name.into(), direction: Direction::Output, indices: Some(indices) }
+    }
+}
+
+/// What a port is connected to in the enclosing circuit
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Binding {
+    Handle(Handle),
+    Bus(Bus),
+}
+
+impl From<Handle> for Binding {
+    fn from(handle: Handle) -> Self {
+        Binding::Handle(handle)
+    }
+}
+
+impl From<Bus> for Binding {
+    fn from(bus: Bus) -> Self {
+        Binding::Bus(bus)
+    }
+}
+
+impl Binding {
+    /// Handle that a use of a port with `index` inside an instance stands for, or `None` if the
+    /// port declares no such index
+    pub(super) fn resolve(&self, index: Option<i8>, indices: &Option<RangeInclusive<i8>>) -> Option<Handle> {
+        match (self, index, indices) {
+            (Binding::Handle(handle), None, None) => Some(handle.clone()),
+            (Binding::Bus(bus), Some(index), Some(indices)) if indices.contains(&index) => Some(bus.index(index)),
+            _ => None,
+        }
+    }
+
+    /// Handles the port stands for across all of its declared indices
+    fn members(&self, indices: &Option<RangeInclusive<i8>>) -> Vec<Handle> {
+        match indices {
+            None => self.resolve(None, indices).into_iter().collect(),
+            Some(range) => range.clone().filter_map(|index| self.resolve(Some(index), indices)).collect(),
+        }
+    }
+}
+
+/// A reusable piece of circuit with declared ports
+///
+/// `build` runs in a scope named after the instance, as in `CircuitBuilder::add_scoped_subcircuit`,
+/// where each port name stands for what it is bound to: with `From` bound to the bus `Af`, the coil
+/// `From_3` is `Af_3` and the switch `from_3` is `af_3`. Using an index the port does not declare
+/// is a `PortMismatch`, and every output port needs a coil or contact of the instance on at least
+/// one of its members.
+pub trait Subcircuit {
+    fn name(&self) -> String;
+
+    fn ports(&self) -> Vec<Port>;
+
+    fn build(&self, scb: SubcircuitBuilder);
+}
+
+impl CircuitBuilder {
+
+    /// Adds an instance of `subcircuit` named `instance`, panicking if its ports are not all bound
+    pub fn instantiate<'b>(self, instance: &str, subcircuit: &impl Subcircuit, bindings: impl IntoIterator<Item = (&'b str, Binding)>) -> Self {
+        self.try_instantiate(instance, subcircuit, bindings).unwrap_or_else(|err| panic!("{}", err))
+    }
+
+    /// Like `instantiate`, but reports missing, unknown, mismatched and undriven ports and instance
+    /// names already in use
+    pub fn try_instantiate<'b>(mut self, instance: &str, subcircuit: &impl Subcircuit, bindings: impl IntoIterator<Item = (&'b str, Binding)>)
+        -> Result<Self, Z3muError> {
+        let error = |port: &str| (instance.to_string(), subcircuit.name(), port.to_string());
+        let mut bindings: HashMap<String, Binding> = bindings.into_iter().map(|(port, binding)| (port.to_string(), binding)).collect();
+        let mut bound = HashMap::new();
+        for port in subcircuit.ports() {
+            let (instance, subcircuit, port_name) = error(&port.name);
+            match (bindings.remove(&port.name), &port.indices) {
+                (None, _) => return Err(Z3muError::UnboundPort { instance, subcircuit, port: port_name }),
+                (Some(binding @ Binding::Handle(_)), None) | (Some(binding @ Binding::Bus(_)), Some(_)) => {
+                    bound.insert(port.name, (binding, port.indices));
+                }
+                (Some(_), _) => return Err(Z3muError::PortMismatch { instance, subcircuit, port: port_name }),
+            }
+        }
+        let mut unknown: Vec<String> = bindings.into_keys().collect();
+        unknown.sort();
+        if let Some(port) = unknown.first() {
+            let (instance, subcircuit, port) = error(port);
+            return Err(Z3muError::UnknownPort { instance, subcircuit, port });
+        }
+        self.claim_scope(instance, instance)?;
+
+        // outputs are driven by coils and contacts that the instance itself adds
+        let outputs: Vec<(String, Vec<Handle>)> = subcircuit
+            .ports()
+            .into_iter()
+            .filter(|port| port.direction == Direction::Output)
+            .map(|port| {
+                let (binding, indices) = &bound[&port.name];
+                (port.name, binding.members(indices))
+            })
+            .collect();
+        let first_switch = self.switches.len();
+        let first_coil = self.coil_order.len();
+        self.mismatched_port = None;
+        let ret = self.add_scope(Scope::instance(instance, bound), |scb| subcircuit.build(scb));
+
+        if let Some(port) = &ret.mismatched_port {
+            let (instance, subcircuit, port) = error(port);
+            return Err(Z3muError::PortMismatch { instance, subcircuit, port });
+        }
+        let touched: HashSet<NodeId> = ret.switches[first_switch..]
+            .iter()
+            .flat_map(|switch| [switch.pole, switch.no, switch.nc])
+            .chain(ret.coil_order[first_coil..].iter().map(|coil| ret.labels[coil]))
+            .collect();
+        for (port, members) in outputs {
+            if !members.iter().any(|handle| ret.labels.get(handle).is_some_and(|node| touched.contains(node))) {
+                let (instance, subcircuit, port) = error(&port);
+                return Err(Z3muError::UndrivenPort { instance, subcircuit, port });
+            }
+        }
+        Ok(ret)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::Gate;
+
+    /// Feeds `Out` from `In_index`, or only labels it if `drive` is false
+    struct Probe {
+        index: i8,
+        drive: bool,
+    }
+
+    impl Subcircuit for Probe {
+        fn name(&self) -> String {
+            "probe".into()
+        }
+
+        fn ports(&self) -> Vec<Port> {
+            vec![Port::input_bus("In", 0..=3), Port::output("Out")]
+        }
+
+        fn build(&self, mut scb: SubcircuitBuilder) {
+            let input = scb.label(handle!("In", self.index));
+            if self.drive {
+                scb.coil("Out", input);
+            } else {
+                scb.label("Out");
+            }
+        }
+    }
+
+    fn registers(mut scb: SubcircuitBuilder) {
+        for i in 0..=3 {
+            scb.coil(handle!("Af", i), None);
+            for bus in ["Aa", "Ab"] {
+                let node = scb.label(handle!(bus, i));
+                scb.trace(node);
+            }
+        }
+    }
+
+    #[test]
+    fn instances_share_a_definition() {
+        let gate = Gate::new(0..=3);
+        let mut c = CircuitBuilder::new()
+            .add_subcircuit(registers)
+            .instantiate("a", &gate, [("From", bus!("Af").into()), ("Gate", handle!("Ea").into()), ("To", bus!("Aa").into())])
+            .instantiate("b", &gate, [("From", bus!("Af").into()), ("Gate", handle!("Eb").into()), ("To", bus!("Ab").into())])
+            .finalize();
+        assert_eq!(c.relay_contacts(&handle!("af", 2)).len(), 2);
+        assert_eq!(c.relay_contacts(&handle!("eb")).len(), 4);
+
+        c.set_bus(&bus!("Af"), 5);
+        c.set(&handle!("Eb"));
+        c.step();
+        c.set(&handle!("S", 5));
+        c.step();
+        assert_eq!(c.inspect_bus(&bus!("Aa")), 0);
+        assert_eq!(c.inspect_bus(&bus!("Ab")), 5);
+    }
+
+    #[test]
+    fn checks_port_bindings() {
+        let gate = Gate::new(0..=3);
+        let error = |bindings: Vec<(&str, Binding)>| CircuitBuilder::new().try_instantiate("a", &gate, bindings).err().unwrap().to_string();
+        assert_eq!(error(vec![("From", bus!("Af").into()), ("Gate", handle!("Ea").into())]),
+                   "port To of a (gate) is not connected");
+        assert_eq!(error(vec![("From", bus!("Af").into()), ("Gate", bus!("Ea").into()), ("To", bus!("Aa").into())]),
+                   "port Gate of a (gate) is bound or used in a way its declaration does not allow");
+        assert_eq!(error(vec![("From", bus!("Af").into()), ("Gate", handle!("Ea").into()), ("To", bus!("Aa").into()), ("Xy", handle!("Xy").into())]),
+                   "a (gate) has no port Xy");
+    }
+
+    #[test]
+    fn checks_port_uses() {
+        let error = |builder: CircuitBuilder, instance: &str, probe: Probe| builder
+            .try_instantiate(instance, &probe, [("In", bus!("Ib").into()), ("Out", handle!("Ob").into())])
+            .err()
+            .map(|err| err.to_string());
+        assert_eq!(error(CircuitBuilder::new(), "p", Probe { index: 3, drive: true }), None);
+        assert_eq!(error(CircuitBuilder::new(), "p", Probe { index: 4, drive: true }).unwrap(),
+                   "port In of p (probe) is bound or used in a way its declaration does not allow");
+        assert_eq!(error(CircuitBuilder::new(), "p", Probe { index: 0, drive: false }).unwrap(),
+                   "output Out of p (probe) is not driven by any coil or contact");
+
+        // a coil added outside the instance does not drive its output, however often it is labelled
+        let outside = CircuitBuilder::new().add_subcircuit(|mut scb| {
+            scb.coil("Ob", None);
+        });
+        assert_eq!(error(outside, "p", Probe { index: 0, drive: false }).unwrap(),
+                   "output Out of p (probe) is not driven by any coil or contact");
+
+        let first = CircuitBuilder::new().instantiate("p", &Probe { index: 0, drive: true }, [("In", bus!("Ia").into()), ("Out", handle!("Oa").into())]);
+        assert_eq!(error(first, "p", Probe { index: 1, drive: true }).unwrap(), "scope p is already in use");
+    }
+}
@@ -0,0 +1,120 @@
+use std::collections::HashMap;
+
+use super::{unknown_label, Circuit, CircuitBuilder, Handle, NodeId, Z3muError};
+
+/// One changeover contact set of a relay and where it stands
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RelayContact {
+    pub pole: NodeId,
+    pub no: NodeId,
+    pub nc: NodeId,
+    pub picked_up: bool, // pole connected to NO rather than NC
+}
+
+impl Circuit {
+
+    /// Whether the contacts of relay `relay`, e.g. `ba_-3`, are picked up
+    pub fn is_picked_up(&self, relay: &Handle) -> bool {
+        self.try_is_picked_up(relay).unwrap_or_else(|err| panic!("{}", err))
+    }
+
+    /// Like `is_picked_up`, but reports a relay without contacts instead of panicking
+    pub fn try_is_picked_up(&self, relay: &Handle) -> Result<bool, Z3muError> {
+        match self.switches.iter().position(|switch| switch.name == *relay) {
+            Some(id) => Ok(self.switch_positions[id]),
+            None => Err(unknown_label(relay)),
+        }
+    }
+
+    /// Every contact set of relay `relay`, in the order they were added; empty if there are none
+    pub fn relay_contacts(&self, relay: &Handle) -> Vec<RelayContact> {
+        self.switches
+            .iter()
+            .enumerate()
+            .filter(|(_, switch)| switch.name == *relay)
+            .map(|(id, switch)| RelayContact {
+                pole: switch.pole,
+                no: switch.no,
+                nc: switch.nc,
+                picked_up: self.switch_positions[id],
+            })
+            .collect()
+    }
+
+    /// Whether each relay is picked up, keyed by the relay handle of its coils, two-terminal
+    /// coils included, as given by `CircuitBuilder::coil_to_switch_name`; coils without contacts
+    /// are left out
+    pub fn relay_states(&self) -> HashMap<Handle, bool> {
+        let mut positions: HashMap<&Handle, bool> = HashMap::new();
+        for (id, switch) in self.switches.iter().enumerate() {
+            positions.entry(&switch.name).or_insert(self.switch_positions[id]);
+        }
+        let loads = self.electrical.iter().flat_map(|el| el.loads());
+        self.coil_nodes
+            .iter()
+            .map(|(coil, _)| coil)
+            .chain(loads.map(|load| &load.handle))
+            .map(CircuitBuilder::coil_to_switch_name)
+            .filter_map(|relay| positions.get(&relay).map(|&picked_up| (relay, picked_up)))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::circuit::Rail;
+
+    #[test]
+    fn query_relays_by_handle() {
+        let mut c = CircuitBuilder::new()
+            .add_subcircuit(|mut scb| {
+                let (g, input) = (scb.label("G"), scb.label("In"));
+                scb.coil(handle!("Ba", -3), input);
+                scb.coil(handle!("Bb", 1, 1), None);
+                scb.add_switch(handle!("ba", -3), (g, None, None));
+                scb.add_switch(handle!("ba", -3), (input, None, None));
+                scb.add_switch(handle!("bb", 1), (g, None, None));
+                scb.coil(handle!("Bc", 0), None);
+            })
+            .finalize();
+        assert!(!c.is_picked_up(&handle!("ba", -3)));
+        c.set(&handle!("In"));
+        c.step();
+        assert!(c.is_picked_up(&handle!("ba", -3)));
+
+        let contacts = c.relay_contacts(&handle!("ba", -3));
+        assert_eq!(contacts.len(), 2);
+        assert_eq!(contacts[1].pole, c.node_of(&handle!("In")).unwrap());
+        assert!(contacts.iter().all(|contact| contact.picked_up));
+        assert!(c.relay_contacts(&handle!("bc", 0)).is_empty());
+
+        let states = c.relay_states();
+        assert_eq!(states.len(), 2);
+        assert!(states[&handle!("ba", -3)]);
+        assert!(!states[&handle!("bb", 1)]);
+    }
+
+    #[test]
+    fn relay_states_include_two_terminal_coils() {
+        let mut c = CircuitBuilder::new()
+            .electrical()
+            .add_subcircuit(|mut scb| {
+                let (g, minus) = (scb.label("G"), scb.label("minus"));
+                scb.tie(minus, Rail::Negative);
+                scb.coil_between(handle!("Bb", 0), g, minus);
+                scb.add_switch("bb_0", (g, None, None));
+            })
+            .finalize();
+        c.step();
+        assert_eq!(c.relay_states(), HashMap::from([(handle!("bb", 0), true)]));
+    }
+
+    #[test]
+    #[should_panic(expected = "Could not find node")]
+    fn unknown_relay() {
+        let c = CircuitBuilder::new().finalize();
+        assert_eq!(c.try_is_picked_up(&handle!("xy", 0)), Err(unknown_label(&handle!("xy", 0))));
+        c.is_picked_up(&handle!("xy", 0));
+    }
+}
@@ -0,0 +1,291 @@
+use std::collections::HashMap;
+use std::ops::RangeInclusive;
+
+use super::{Binding, CircuitBuilder, Handle, SubcircuitBuilder, Z3muError};
+use super::traces::glob_match;
+
+/// Namespace of a scoped subcircuit
+///
+/// Labels, coils and switches made in a scope are prefixed with its path, e.g. `fig7/+1`, unless
+/// they name a port of the instance being built or match one of the globals declared in it or in
+/// an enclosing scope.
+#[derive(Clone, Debug)]
+pub(super) struct Scope {
+    path: String,
+    globals: Vec<String>, // glob patterns over handles, e.g. `S_*`
+    ports: HashMap<String, (Binding, Option<RangeInclusive<i8>>)>, // port name -> what the instance binds it to, declared indices
+}
+
+impl Scope {
+    fn new(path: &str) -> Scope {
+        Scope { path: path.to_string(), globals: Vec::new(), ports: HashMap::new() }
+    }
+
+    /// Scope of an instance of a `Subcircuit` with its ports bound
+    pub(super) fn instance(path: &str, ports: HashMap<String, (Binding, Option<RangeInclusive<i8>>)>) -> Scope {
+        Scope { ports, ..Scope::new(path) }
+    }
+
+    fn child(&self, name: &str) -> Scope {
+        Scope { path: format!("{}/{}", self.path, name), ..self.clone() }
+    }
+
+    fn is_global(&self, handle: &Handle) -> bool {
+        let text = handle.to_string();
+        self.globals.iter().any(|pattern| glob_match(pattern.as_bytes(), text.as_bytes()))
+    }
+
+    /// Switches belong to the relay of the coil they are named after, so those of a global coil
+    /// such as `Ba_1` are global as well, whether or not the coil has been added yet
+    fn is_global_switch(&self, switch: &Handle) -> bool {
+        self.is_global(switch) || self.is_global(&switch_to_coil_name(switch))
+    }
+
+    /// Port a handle refers to, e.g. `From` for `From_3`, or for `from_3` if it names a switch
+    fn port(&self, handle: &Handle, switch: bool) -> Option<(&String, &Binding, &Option<RangeInclusive<i8>>)> {
+        self.ports
+            .iter()
+            .find(|(port, _)| if switch { port.to_lowercase() == handle.name } else { **port == handle.name })
+            .map(|(port, (binding, indices))| (port, binding, indices))
+    }
+
+    /// Port a handle uses with an index that it does not declare, or with none where it needs one
+    fn mismatched_port(&self, handle: &Handle, switch: bool) -> Option<String> {
+        let (port, binding, indices) = self.port(handle, switch)?;
+        binding.resolve(handle.index, indices).is_none().then(|| port.clone())
+    }
+
+    fn qualify(&self, handle: Handle) -> Handle {
+        if let Some(bound) = self.port(&handle, false).and_then(|(_, binding, indices)| binding.resolve(handle.index, indices)) {
+            bound
+        } else if self.is_global(&handle) {
+            handle
+        } else {
+            self.prefix(handle)
+        }
+    }
+
+    /// Like `qualify`, where a switch stands for the relay of a port, e.g. `from_3` for `From_3`
+    fn qualify_switch(&self, switch: Handle) -> Handle {
+        if let Some(bound) = self.port(&switch, true).and_then(|(_, binding, indices)| binding.resolve(switch.index, indices)) {
+            CircuitBuilder::coil_to_switch_name(&bound)
+        } else if self.is_global_switch(&switch) {
+            switch
+        } else {
+            self.prefix(switch)
+        }
+    }
+
+    fn prefix(&self, handle: Handle) -> Handle {
+        Handle { name: format!("{}/{}", self.path, handle.name), ..handle }
+    }
+}
+
+/// Coil a switch is named after, e.g. `Ba_1` for `ba_1`; the inverse of
+/// `CircuitBuilder::coil_to_switch_name` for names in the form of Section 2.1
+fn switch_to_coil_name(switch: &Handle) -> Handle {
+    let mut chars = switch.name.chars();
+    let name = match chars.next() {
+        Some(first) => first.to_uppercase().chain(chars).collect(),
+        None => String::new(),
+    };
+    Handle { name, ..switch.clone() }
+}
+
+impl CircuitBuilder {
+
+    /// Adds a subcircuit whose labels are local to it
+    ///
+    /// Nodes labelled in `build` are only shared with other subcircuits when their handles were
+    /// first declared with `SubcircuitBuilder::global`; `G` and the clock lines are no exception.
+    /// Local handles are reported with the scope name as a path, e.g. `fig7/+1`.
+    pub fn add_scoped_subcircuit<F: FnOnce(SubcircuitBuilder)>(self, name: impl Into<String>, build: F) -> Self {
+        self.try_add_scoped_subcircuit(name, build).unwrap_or_else(|err| panic!("{}", err))
+    }
+
+    /// Like `add_scoped_subcircuit`, but reports a malformed name or one already in use
+    pub fn try_add_scoped_subcircuit<F: FnOnce(SubcircuitBuilder)>(mut self, name: impl Into<String>, build: F) -> Result<Self, Z3muError> {
+        let name = name.into();
+        self.claim_scope(&name, &name)?;
+        Ok(self.add_scope(Scope::new(&name), build))
+    }
+
+    /// Records that the scope at `path`, whose last segment is `name`, is being built
+    pub(super) fn claim_scope(&mut self, path: &str, name: &str) -> Result<(), Z3muError> {
+        if name.is_empty() || name.contains(['_', '^', '/', '#']) || name.contains(char::is_whitespace) {
+            return Err(Z3muError::InvalidScope { scope: name.to_string() });
+        }
+        if !self.scopes.insert(path.to_string()) {
+            return Err(Z3muError::DuplicateScope { scope: path.to_string() });
+        }
+        Ok(())
+    }
+
+    pub(super) fn add_scope<F: FnOnce(SubcircuitBuilder)>(mut self, scope: Scope, build: F) -> Self {
+        self.subcircuits.push(scope.path.clone());
+        let scb = SubcircuitBuilder { cb: &mut self, scope: Some(scope) };
+        build(scb);
+        self
+    }
+}
+
+impl<'a> SubcircuitBuilder<'a> {
+
+    /// Declares that handles matching `pattern`, a glob such as `G`, `S_*` or `Ba_*`, refer to the
+    /// nodes of the whole circuit; does nothing outside a scope, where every handle is global
+    ///
+    /// Switches are global when their name matches, or when the name of the coil they are named
+    /// after does, so `Ba_*` covers `ba_1` and `Fp` covers `fp` wherever their coils are added.
+    pub fn global(&mut self, pattern: &str) {
+        if let Some(scope) = &mut self.scope {
+            scope.globals.push(pattern.to_string());
+        }
+    }
+
+    /// Builds a nested scope, e.g. `fig7/shifter`, that inherits the globals declared so far
+    pub fn scoped<F: FnOnce(SubcircuitBuilder)>(&mut self, name: &str, build: F) {
+        self.try_scoped(name, build).unwrap_or_else(|err| panic!("{}", err))
+    }
+
+    /// Like `scoped`, but reports a malformed name or one already in use
+    pub fn try_scoped<F: FnOnce(SubcircuitBuilder)>(&mut self, name: &str, build: F) -> Result<(), Z3muError> {
+        let scope = match &self.scope {
+            Some(scope) => scope.child(name),
+            None => Scope::new(name),
+        };
+        self.cb.claim_scope(&scope.path, name)?;
+        build(SubcircuitBuilder { cb: self.cb, scope: Some(scope) });
+        Ok(())
+    }
+
+    /// Fully qualified form of a label or coil handle used in this subcircuit
+    pub fn qualify(&self, handle: Handle) -> Handle {
+        match &self.scope {
+            Some(scope) => scope.qualify(handle),
+            None => handle,
+        }
+    }
+
+    /// Like `qualify`, noting a port used with an index it does not declare
+    pub(super) fn qualify_use(&mut self, handle: Handle) -> Handle {
+        if let Some(port) = self.scope.as_ref().and_then(|scope| scope.mismatched_port(&handle, false)) {
+            self.cb.mismatched_port.get_or_insert(port);
+        }
+        self.qualify(handle)
+    }
+
+    pub(super) fn qualify_switch(&mut self, switch: Handle) -> Handle {
+        match &self.scope {
+            Some(scope) => {
+                if let Some(port) = scope.mismatched_port(&switch, true) {
+                    self.cb.mismatched_port.get_or_insert(port);
+                }
+                scope.qualify_switch(switch)
+            }
+            None => switch,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn shifter(mut scb: SubcircuitBuilder) {
+        scb.global("G");
+        scb.global("Ba_*");
+        let g = scb.label("G");
+        let input = scb.label("+1");
+        scb.coil("Fp", input);
+        let ba = scb.coil(handle!("Ba", 1), None);
+        scb.add_switch("fp", (g, ba, None));
+        scb.add_switch("ba_1", (g, None, None));
+    }
+
+    #[test]
+    fn labels_are_local_to_scopes() {
+        let mut c = CircuitBuilder::new()
+            .add_scoped_subcircuit("fig6", shifter)
+            .add_scoped_subcircuit("fig7", |mut scb| {
+                scb.global("G");
+                shifter(scb);
+            })
+            .add_subcircuit(|mut scb| {
+                let ba = scb.label(handle!("Ba", 1));
+                scb.trace(ba);
+                scb.scoped("inner", |mut scb| {
+                    let spare = scb.label("Spare");
+                    scb.scoped("deeper", |scb| {
+                        assert_eq!(scb.qualify(handle!("x")), handle!("inner/deeper/x"));
+                    });
+                    scb.trace(spare);
+                });
+            })
+            .finalize();
+
+        let fig6 = c.node_of(&handle!("fig6/+1")).unwrap();
+        let fig7 = c.node_of(&handle!("fig7/+1")).unwrap();
+        assert_ne!(fig6, fig7);
+        assert!(c.node_of(&handle!("+1")).is_none());
+        assert_eq!(c.node_name(fig7), "fig7/+1");
+        assert_eq!(c.node_info(fig7).subcircuit.as_deref(), Some("fig7"));
+        assert_eq!(c.relay_contacts(&handle!("fig7/fp")).len(), 1);
+        assert_eq!(c.relay_contacts(&handle!("ba", 1)).len(), 2);
+
+        // only fig7 is fed, yet both shifters share the global Ba_1
+        c.set(&handle!("fig7/+1"));
+        c.step();
+        assert!(c.is_picked_up(&handle!("fig7/fp")));
+        assert!(!c.is_picked_up(&handle!("fig6/fp")));
+        c.step();
+        assert!(c.inspect(&handle!("Ba", 1)));
+        assert_eq!(c.traced(), vec![("Ba_1".to_string(), true), ("inner/Spare".to_string(), false)]);
+    }
+
+    #[test]
+    fn coil_patterns_cover_switches_added_before_their_coils() {
+        let mut c = CircuitBuilder::new()
+            .add_scoped_subcircuit("fig7", |mut scb| {
+                scb.global("G");
+                scb.global("Ba_*");
+                scb.global("Fp");
+                let g = scb.label("G");
+                scb.add_switch("fp", (g, None, None));
+                scb.add_switch(handle!("ba", 1), (g, None, None));
+                scb.add_switch("fq", (g, None, None));
+            })
+            .add_subcircuit(|mut scb| {
+                let g = scb.label("G");
+                scb.coil("Fp", g);
+                scb.coil(handle!("Ba", 1), None);
+            })
+            .finalize();
+        assert_eq!(c.relay_contacts(&handle!("fp")).len(), 1);
+        assert_eq!(c.relay_contacts(&handle!("ba", 1)).len(), 1);
+        assert_eq!(c.relay_contacts(&handle!("fig7/fq")).len(), 1);
+        c.step();
+        assert!(c.is_picked_up(&handle!("fp")));
+    }
+
+    #[test]
+    fn scope_names_are_used_once() {
+        let error = CircuitBuilder::new()
+            .add_scoped_subcircuit("fig7", |mut scb| {
+                scb.scoped("inner", |_| {});
+                assert_eq!(scb.try_scoped("inner", |_| {}), Err(Z3muError::DuplicateScope { scope: "fig7/inner".into() }));
+                assert_eq!(scb.try_scoped("in_ner", |_| {}), Err(Z3muError::InvalidScope { scope: "in_ner".into() }));
+                for name in ["fig 7", "fig#7", "fig\t7"] {
+                    assert_eq!(scb.try_scoped(name, |_| {}), Err(Z3muError::InvalidScope { scope: name.into() }));
+                }
+            })
+            .try_add_scoped_subcircuit("fig7", |_| {})
+            .err()
+            .unwrap();
+        assert_eq!(error.to_string(), "scope fig7 is already in use");
+    }
+
+    #[test]
+    fn local_coils_drive_local_switches() {
+        assert_eq!(CircuitBuilder::coil_to_switch_name(&handle!("Fig7/Bb", 1, 1)), handle!("Fig7/bb", 1));
+    }
+}
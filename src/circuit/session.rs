@@ -0,0 +1,332 @@
+use std::path::Path;
+use std::str::FromStr;
+
+use super::{Bus, Circuit, Handle, Z3muError};
+use super::error::{versioned_lines, FileError};
+
+/// Version written in the header of session logs and the only one `Session` can read
+pub const SESSION_VERSION: u32 = 1;
+
+/// Inputs applied to a `Circuit` while it was recording, with the traced outputs after each step
+///
+/// The text form is one call per line after a `z3mu session <version>` header. Every `step`,
+/// `settle` and `tick` is followed by the traced nodes it produced:
+///
+/// ```text
+/// z3mu session 1
+/// set_bus Ab 6
+/// set Ga
+/// step Aa_0=0 Aa_1=1 Ga=1
+/// ```
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Session {
+    pub entries: Vec<SessionEntry>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SessionEntry {
+    Set(Handle),
+    SetBus(Bus, i32),
+    Hold(Handle),
+    Release(Handle),
+    Step(Vec<(String, bool)>), // traced nodes after the step, sorted by name
+    Settle(usize, Vec<(String, bool)>), // tick limit, and traced nodes after settling
+    Tick(Vec<(String, bool)>), // traced nodes after a tick taken outside a step
+    Trace(Handle),
+    Untrace(Handle),
+    TraceBus(Bus),
+    UntraceBus(Bus),
+    TraceMatching(String), // glob pattern
+    UntraceMatching(String),
+    TraceEverything,
+    UntraceEverything,
+}
+
+#[derive(Debug)]
+pub enum ReplayError {
+    File(FileError),
+    Diverged { step: u64, node: String, expected: bool, found: Option<bool> }, // found is None if untraced
+    Rejected { step: u64, error: Z3muError }, // a call the circuit refused, after `step` steps
+}
+
+impl std::fmt::Display for ReplayError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ReplayError::File(err) => write!(f, "session log: {}", err),
+            ReplayError::Diverged { step, node, expected, found: Some(found) } =>
+                write!(f, "replay diverged at step {}: {} is {} but was {}", step, node, *found as u8, *expected as u8),
+            ReplayError::Diverged { step, node, found: None, .. } =>
+                write!(f, "replay diverged at step {}: {} is not traced", step, node),
+            ReplayError::Rejected { step, error } => write!(f, "replay failed after step {}: {}", step, error),
+        }
+    }
+}
+
+impl std::error::Error for ReplayError {}
+
+impl From<FileError> for ReplayError {
+    fn from(err: FileError) -> Self {
+        ReplayError::File(err)
+    }
+}
+
+impl Session {
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), ReplayError> {
+        Ok(std::fs::write(path, self.to_string()).map_err(FileError::Io)?)
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<Session, ReplayError> {
+        std::fs::read_to_string(path).map_err(FileError::Io)?.parse()
+    }
+}
+
+impl std::fmt::Display for Session {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "z3mu session {}", SESSION_VERSION)?;
+        for entry in &self.entries {
+            match entry {
+                SessionEntry::Set(handle) => writeln!(f, "set {}", handle)?,
+                SessionEntry::SetBus(bus, k) => writeln!(f, "set_bus {} {}", bus, k)?,
+                SessionEntry::Hold(handle) => writeln!(f, "hold {}", handle)?,
+                SessionEntry::Release(handle) => writeln!(f, "release {}", handle)?,
+                SessionEntry::Trace(handle) => writeln!(f, "trace {}", handle)?,
+                SessionEntry::Untrace(handle) => writeln!(f, "untrace {}", handle)?,
+                SessionEntry::TraceBus(bus) => writeln!(f, "trace_bus {}", bus)?,
+                SessionEntry::UntraceBus(bus) => writeln!(f, "untrace_bus {}", bus)?,
+                SessionEntry::TraceMatching(pattern) => writeln!(f, "trace_matching {}", pattern)?,
+                SessionEntry::UntraceMatching(pattern) => writeln!(f, "untrace_matching {}", pattern)?,
+                SessionEntry::TraceEverything => writeln!(f, "trace_everything")?,
+                SessionEntry::UntraceEverything => writeln!(f, "untrace_everything")?,
+                SessionEntry::Step(traces) => {
+                    write!(f, "step")?;
+                    write_traces(f, traces)?;
+                }
+                SessionEntry::Settle(limit, traces) => {
+                    write!(f, "settle {}", limit)?;
+                    write_traces(f, traces)?;
+                }
+                SessionEntry::Tick(traces) => {
+                    write!(f, "tick")?;
+                    write_traces(f, traces)?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+fn write_traces(f: &mut std::fmt::Formatter<'_>, traces: &[(String, bool)]) -> std::fmt::Result {
+    for (node, powered) in traces {
+        write!(f, " {}={}", node, *powered as u8)?;
+    }
+    writeln!(f)
+}
+
+impl FromStr for Session {
+    type Err = ReplayError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut ret = Session::default();
+        for (line, text) in versioned_lines(s, "session", SESSION_VERSION)? {
+            let err = |message: String| ReplayError::File(FileError::at_line(line, message));
+            let fields: Vec<&str> = text.split_whitespace().collect();
+            let handle = |i: usize| fields
+                .get(i)
+                .and_then(|field| field.parse().ok())
+                .ok_or_else(|| err(format!("expected a handle after \"{}\"", fields[0])));
+            let bus = || match handle(1)? {
+                Handle { name, index: None, sup } => Ok(Bus::new(name, sup)),
+                other => Err(err(format!("bus {} must not have an index", other))),
+            };
+            let pattern = || fields.get(1).map(|pattern| pattern.to_string()).ok_or_else(|| err("expected a pattern".into()));
+            let traces = |from: usize| fields[from.min(fields.len())..].iter().map(|field| match field.rsplit_once('=') {
+                Some((node, "0")) => Ok((node.to_string(), false)),
+                Some((node, "1")) => Ok((node.to_string(), true)),
+                _ => Err(err(format!("expected <node>=<0|1>, found \"{}\"", field))),
+            }).collect::<Result<Vec<_>, _>>();
+            let entry = match fields[0] {
+                "set" => SessionEntry::Set(handle(1)?),
+                "hold" => SessionEntry::Hold(handle(1)?),
+                "release" => SessionEntry::Release(handle(1)?),
+                "trace" => SessionEntry::Trace(handle(1)?),
+                "untrace" => SessionEntry::Untrace(handle(1)?),
+                "set_bus" => {
+                    let k = fields.get(2).and_then(|k| k.parse().ok()).ok_or_else(|| err("expected a bus value".into()))?;
+                    SessionEntry::SetBus(bus()?, k)
+                }
+                "trace_bus" => SessionEntry::TraceBus(bus()?),
+                "untrace_bus" => SessionEntry::UntraceBus(bus()?),
+                "trace_matching" => SessionEntry::TraceMatching(pattern()?),
+                "untrace_matching" => SessionEntry::UntraceMatching(pattern()?),
+                "trace_everything" => SessionEntry::TraceEverything,
+                "untrace_everything" => SessionEntry::UntraceEverything,
+                "step" => SessionEntry::Step(traces(1)?),
+                "tick" => SessionEntry::Tick(traces(1)?),
+                "settle" => {
+                    let limit = fields.get(1).and_then(|limit| limit.parse().ok()).ok_or_else(|| err("expected a tick limit".into()))?;
+                    SessionEntry::Settle(limit, traces(2)?)
+                }
+                other => return Err(err(format!("unknown call \"{}\"", other))),
+            };
+            ret.entries.push(entry);
+        }
+        Ok(ret)
+    }
+}
+
+impl Circuit {
+
+    /// Starts logging every `set`, `set_bus`, `hold`, `release`, `step`, `settle` and `tick`
+    /// call, and every change to the traced nodes, replacing any session being recorded
+    pub fn start_recording(&mut self) {
+        self.recording = Some(Session::default());
+    }
+
+    pub fn stop_recording(&mut self) -> Option<Session> {
+        self.recording.take()
+    }
+
+    pub(super) fn record_entry(&mut self, entry: impl FnOnce(&Circuit) -> SessionEntry) {
+        if let Some(mut session) = self.recording.take() {
+            session.entries.push(entry(self));
+            self.recording = Some(session);
+        }
+    }
+
+    /// Applies a recorded session, checking after every step that the nodes traced when it was
+    /// recorded have the same states here
+    pub fn replay(&mut self, session: &Session) -> Result<(), ReplayError> {
+        for entry in &session.entries {
+            match entry {
+                SessionEntry::Set(handle) => self.try_set(handle).map_err(|error| self.rejected(error))?,
+                SessionEntry::SetBus(bus, k) => self.try_set_bus(bus, *k).map_err(|error| self.rejected(error))?,
+                SessionEntry::Hold(handle) => self.try_hold(handle).map_err(|error| self.rejected(error))?,
+                SessionEntry::Release(handle) => self.release(handle),
+                SessionEntry::Trace(handle) => self.try_trace(handle).map_err(|error| self.rejected(error))?,
+                SessionEntry::Untrace(handle) => self.try_untrace(handle).map_err(|error| self.rejected(error))?,
+                SessionEntry::TraceBus(bus) => self.trace_bus(bus),
+                SessionEntry::UntraceBus(bus) => self.untrace_bus(bus),
+                SessionEntry::TraceMatching(pattern) => {
+                    self.trace_matching(pattern);
+                }
+                SessionEntry::UntraceMatching(pattern) => {
+                    self.untrace_matching(pattern);
+                }
+                SessionEntry::TraceEverything => self.trace_everything(),
+                SessionEntry::UntraceEverything => self.untrace_everything(),
+                SessionEntry::Tick(expected) => {
+                    self.tick();
+                    self.check_traces(expected)?;
+                }
+                SessionEntry::Step(expected) => {
+                    self.step();
+                    self.check_traces(expected)?;
+                }
+                SessionEntry::Settle(limit, expected) => {
+                    // an unstable circuit is a divergence only if it leaves different traces
+                    let _ = self.settle_within(*limit);
+                    self.check_traces(expected)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn rejected(&self, error: Z3muError) -> ReplayError {
+        ReplayError::Rejected { step: self.steps, error }
+    }
+
+    fn check_traces(&self, expected: &[(String, bool)]) -> Result<(), ReplayError> {
+        let traced = self.traced();
+        for (node, expected) in expected {
+            let found = traced
+                .binary_search_by(|(name, _)| name.as_str().cmp(node))
+                .ok()
+                .map(|i| traced[i].1);
+            if found != Some(*expected) {
+                return Err(ReplayError::Diverged { step: self.steps, node: node.clone(), expected: *expected, found });
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::circuit::CircuitBuilder;
+    use crate::common::{gate, gate_const};
+
+    fn build(constant: bool) -> Circuit {
+        let mut builder = CircuitBuilder::new();
+        builder = if constant {
+            builder.add_subcircuit(gate_const(6, handle!("Ga"), bus!("Aa"), 0..=3))
+        } else {
+            builder.add_subcircuit(gate(bus!("Ab"), handle!("Ga"), bus!("Aa"), 0..=3))
+        };
+        builder
+            .add_subcircuit(|mut scb| {
+                for i in 0..=3 {
+                    scb.coil(handle!("Ab", i), None);
+                    let aa = scb.label(handle!("Aa", i));
+                    scb.trace(aa);
+                }
+            })
+            .finalize()
+    }
+
+    fn drive(c: &mut Circuit, k: i32) {
+        c.set_bus(&bus!("Ab"), k);
+        c.hold(&handle!("Ga"));
+        c.step();
+        c.set(&handle!("S", 5));
+        c.step();
+        c.release(&handle!("Ga"));
+        c.step();
+    }
+
+    #[test]
+    fn replay_reproduces_session() {
+        let mut c = build(false);
+        c.start_recording();
+        drive(&mut c, 6);
+        drive(&mut c, 5);
+        let session = c.stop_recording().unwrap();
+        assert_eq!(session.entries.iter().filter(|entry| matches!(entry, SessionEntry::Step(_))).count(), 6);
+
+        let text = session.to_string();
+        assert!(text.starts_with("z3mu session 1\nset_bus Ab 6\nhold Ga\nstep Aa_0=0 Aa_1=0 Aa_2=0 Aa_3=0\nset S_5\nstep Aa_0=0 Aa_1=1 Aa_2=1 Aa_3=0\n"));
+        let parsed: Session = text.parse().unwrap();
+        assert_eq!(parsed, session);
+        build(false).replay(&parsed).unwrap();
+
+        // a constant gate matches the first value but not the second
+        match build(true).replay(&parsed) {
+            Err(ReplayError::Diverged { step: 5, node, expected: true, found: Some(false) }) => assert_eq!(node, "Aa_0"),
+            other => panic!("unexpected replay result {:?}", other),
+        }
+    }
+
+    #[test]
+    fn replay_covers_traces_and_ticks() {
+        let mut c = build(false);
+        c.start_recording();
+        c.untrace_matching("Aa_*");
+        c.trace(&handle!("Ab", 1));
+        c.set_bus(&bus!("Ab"), 2);
+        c.tick();
+        c.trace_bus(&bus!("Aa"));
+        drive(&mut c, 6);
+        let session = c.stop_recording().unwrap();
+        let text = session.to_string();
+        assert!(text.starts_with("z3mu session 1\nuntrace_matching Aa_*\ntrace Ab_1\nset_bus Ab 2\ntick Ab_1=1\ntrace_bus Aa\n"));
+        build(false).replay(&text.parse().unwrap()).unwrap();
+
+        // a hand-edited log naming a label the circuit lacks is refused rather than panicking
+        let edited: Session = "z3mu session 1\nstep\nhold Gb\nstep\n".parse().unwrap();
+        match build(false).replay(&edited) {
+            Err(ReplayError::Rejected { step: 1, error }) => assert_eq!(error.to_string(), "Could not find node \"Gb\""),
+            other => panic!("unexpected replay result {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,172 @@
+use super::{Circuit, SessionEntry};
+
+/// Number of ticks `Circuit::settle` waits for the relays to come to rest
+pub const DEFAULT_SETTLE_LIMIT: usize = 1000;
+
+/// The relays were still moving when `Circuit::settle` gave up
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Unstable {
+    pub iterations: usize,
+}
+
+impl std::fmt::Display for Unstable {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "circuit did not settle within {} iterations", self.iterations)
+    }
+}
+
+impl std::error::Error for Unstable {}
+
+impl Circuit {
+
+    /// Tick until no switch moves, holding the pending sources high throughout
+    ///
+    /// With a phase clock attached, the lines of the current phase are held high as well.
+    /// Returns the number of ticks taken, including the final one in which nothing moved.
+    ///
+    /// Settling counts as one step of however many ticks it took: observers, the history and a
+    /// recording session see it like any other step, except that the clock does not advance.
+    pub fn settle(&mut self) -> Result<usize, Unstable> {
+        self.settle_within(DEFAULT_SETTLE_LIMIT)
+    }
+
+    /// Like `settle`, giving up after `limit` ticks
+    ///
+    /// A limit of 0 is rejected as unstable without ticking or counting a step.
+    pub fn settle_within(&mut self, limit: usize) -> Result<usize, Unstable> {
+        if limit == 0 {
+            return Err(Unstable { iterations: 0 });
+        }
+        self.observers.step_started(self.steps + 1);
+        let mut settle_sources = std::mem::take(&mut self.step_sources);
+        settle_sources.clear();
+        settle_sources.extend_from_slice(&self.sources);
+        let mut prev_positions = self.switch_positions.clone();
+        let mut result = Err(Unstable { iterations: limit });
+        for iteration in 1..=limit {
+            if iteration > 1 {
+                self.sources.extend_from_slice(&settle_sources);
+            }
+            self.run_tick();
+            if self.switch_positions == prev_positions && self.armatures.at_rest() {
+                result = Ok(iteration);
+                break;
+            }
+            prev_positions.clone_from(&self.switch_positions);
+        }
+        self.step_sources = settle_sources;
+        self.end_step(|c| SessionEntry::Settle(limit, c.traced()));
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::circuit::{CircuitBuilder, Engine, Handle, SubcircuitBuilder, Timing};
+
+    /// Relays Aa_0..Aa_4 each picking up the next one, so a pulse on `In` takes five ticks to
+    /// ripple through
+    fn cascade(timing: Timing) -> Circuit {
+        CircuitBuilder::new().add_subcircuit(|mut scb| {
+            let input = scb.label("In");
+            let g = scb.label("G");
+            SubcircuitBuilder::chain(input, 0..5, |coil_pos, i| {
+                scb.coil_with_timing(handle!("Aa", i), coil_pos, timing);
+                let out = scb.label(handle!("out", i));
+                scb.trace(out);
+                scb.add_switch(handle!("aa", i), (g, out, None));
+                out
+            });
+        }).finalize()
+    }
+
+    #[test]
+    fn settle_cascade() {
+        for engine in [Engine::TwoPhase, Engine::EventDriven] {
+            let mut c = cascade(Timing::default());
+            c.set_engine(engine);
+            c.set(&handle!("In"));
+            assert_eq!(c.settle(), Ok(6));
+            for i in 0..5 {
+                assert!(c.inspect(&handle!("out", i)));
+            }
+            // releasing the input drops the cascade back out
+            assert_eq!(c.settle(), Ok(6));
+            assert!(!c.inspect(&handle!("out", 4)));
+            assert_eq!(c.settle(), Ok(1));
+        }
+    }
+
+    #[test]
+    fn settle_is_recorded_as_a_step() {
+        let mut c = cascade(Timing::default());
+        c.record_history(1 << 20);
+        c.start_recording();
+        c.set(&handle!("In"));
+        assert_eq!(c.settle(), Ok(6));
+        assert_eq!((c.steps(), c.ticks()), (1, 6));
+        let session = c.stop_recording().unwrap();
+        assert_eq!(session.to_string(), "z3mu session 1\nset In\nsettle 1000 Aa_1=1 Aa_2=1 Aa_3=1 Aa_4=1 out_4=1\n");
+        cascade(Timing::default()).replay(&session.to_string().parse().unwrap()).unwrap();
+
+        assert!(c.step_back());
+        assert_eq!((c.steps(), c.ticks()), (0, 0));
+        assert!(!c.inspect(&handle!("out", 0)));
+    }
+
+    #[test]
+    fn settle_waits_for_delays() {
+        let mut c = cascade(Timing::new(3, 2));
+        c.set(&handle!("In"));
+        assert_eq!(c.settle(), Ok(16));
+        assert!(c.inspect(&handle!("out", 4)));
+    }
+
+    #[test]
+    fn settle_holds_current_phase() {
+        let mut c = CircuitBuilder::new()
+            .with_phase_clock()
+            .add_subcircuit(|mut scb| {
+                let s3 = scb.label(handle!("S", 3));
+                scb.coil(handle!("Aa", 0), s3);
+                let g = scb.label("G");
+                let out = scb.label("out");
+                scb.trace(out);
+                scb.add_switch(handle!("aa", 0), (g, out, None));
+            })
+            .finalize();
+        for _ in 0..3 {
+            c.step();
+        }
+        assert_eq!(c.phase_clock().unwrap().phase(), 3);
+        // S_3 keeps Aa_0 picked up, so nothing moves
+        assert_eq!(c.settle(), Ok(1));
+        assert!(c.inspect(&handle!("out")));
+    }
+
+    #[test]
+    fn settle_oscillator() {
+        let mut c = CircuitBuilder::new().add_subcircuit(|mut scb| {
+            let g = scb.label("G");
+            let (_, _, coil_node) = scb.add_switch("xy_-10", (g, None, None));
+            scb.coil("Xy_-10", Some(coil_node));
+        }).finalize();
+        assert_eq!(c.settle_within(50), Err(Unstable { iterations: 50 }));
+    }
+
+    #[test]
+    fn settle_within_zero_ticks_changes_nothing() {
+        let mut c = cascade(Timing::default());
+        c.record_history(1 << 20);
+        c.start_recording();
+        c.set(&handle!("In"));
+        assert_eq!(c.settle_within(0), Err(Unstable { iterations: 0 }));
+        assert_eq!((c.steps(), c.ticks()), (0, 0));
+        assert_eq!(c.history_range(), Some(0..=0));
+        assert_eq!(c.stop_recording().unwrap().to_string(), "z3mu session 1\nset In\n");
+        // the pending input is still there for the next step
+        c.step();
+        assert!(c.inspect(&handle!("In")));
+    }
+}
@@ -0,0 +1,424 @@
+use std::collections::HashMap;
+use std::path::Path;
+use std::str::FromStr;
+
+use super::{Circuit, Handle, SwitchId, Terminal};
+use super::error::{versioned_lines, FileError};
+use super::clock::PHASES;
+
+/// Version written in the header of snapshot files and the only one `Snapshot` can read
+pub const SNAPSHOT_VERSION: u32 = 3;
+
+/// Simulation state of a `Circuit`, keyed by handles so that it survives rebuilding the netlist
+///
+/// The text form is one record per line after a `z3mu snapshot <version>` header:
+///
+/// ```text
+/// z3mu snapshot 3
+/// ticks 42
+/// steps 14
+/// clock 8 2
+/// switch ab_0 1 0
+/// held Ei
+/// powered Aa_0
+/// terminal ab_0 0 no
+/// trace Aa_0 1
+/// ```
+///
+/// `switch` records carry the position and the ticks the coil has disagreed with it, in netlist
+/// order, so switches sharing a name are matched up by position. `powered` records name the
+/// labelled nodes that were powered during the last tick, traced or not, and `terminal` records
+/// the unlabelled ones by a switch terminal on them, counting switches of the same name from 0
+/// in netlist order.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Snapshot {
+    pub ticks: u64,
+    pub steps: u64,
+    pub clock: Option<(u64, u8)>, // (cycle, phase) of the phase clock
+    pub switches: Vec<(Handle, bool, u32)>, // (switch, position, held_for) in netlist order
+    pub held: Vec<Handle>,
+    pub powered: Vec<Handle>, // labelled nodes powered during the last tick by their first label, sorted
+    pub powered_terminals: Vec<(Handle, u32, Terminal)>, // other powered nodes by (switch, occurrence, terminal)
+    pub traces: Vec<(Handle, bool)>, // traced nodes by their first label
+}
+
+#[derive(Debug)]
+pub enum SnapshotError {
+    File(FileError),
+    Mismatch(String), // snapshot does not fit the circuit's netlist
+}
+
+impl std::fmt::Display for SnapshotError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SnapshotError::File(err) => write!(f, "snapshot: {}", err),
+            SnapshotError::Mismatch(message) => write!(f, "snapshot does not match the netlist: {}", message),
+        }
+    }
+}
+
+impl std::error::Error for SnapshotError {}
+
+impl From<FileError> for SnapshotError {
+    fn from(err: FileError) -> Self {
+        SnapshotError::File(err)
+    }
+}
+
+impl Snapshot {
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), SnapshotError> {
+        Ok(std::fs::write(path, self.to_string()).map_err(FileError::Io)?)
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<Snapshot, SnapshotError> {
+        std::fs::read_to_string(path).map_err(FileError::Io)?.parse()
+    }
+}
+
+impl std::fmt::Display for Snapshot {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "z3mu snapshot {}", SNAPSHOT_VERSION)?;
+        writeln!(f, "ticks {}", self.ticks)?;
+        writeln!(f, "steps {}", self.steps)?;
+        if let Some((cycle, phase)) = self.clock {
+            writeln!(f, "clock {} {}", cycle, phase)?;
+        }
+        for (switch, position, held_for) in &self.switches {
+            writeln!(f, "switch {} {} {}", switch, *position as u8, held_for)?;
+        }
+        for handle in &self.held {
+            writeln!(f, "held {}", handle)?;
+        }
+        for handle in &self.powered {
+            writeln!(f, "powered {}", handle)?;
+        }
+        for (switch, occurrence, terminal) in &self.powered_terminals {
+            writeln!(f, "terminal {} {} {}", switch, occurrence, terminal)?;
+        }
+        for (handle, powered) in &self.traces {
+            writeln!(f, "trace {} {}", handle, *powered as u8)?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for Snapshot {
+    type Err = SnapshotError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut ret = Snapshot::default();
+        for (line, text) in versioned_lines(s, "snapshot", SNAPSHOT_VERSION)? {
+            let err = |message: String| SnapshotError::File(FileError::at_line(line, message));
+            let fields: Vec<&str> = text.split_whitespace().collect();
+            let number = |i: usize| -> Result<u64, SnapshotError> {
+                fields[i].parse().map_err(|_| err(format!("expected a number, found \"{}\"", fields[i])))
+            };
+            let bit = |i: usize| match fields[i] {
+                "0" => Ok(false),
+                "1" => Ok(true),
+                other => Err(err(format!("expected 0 or 1, found \"{}\"", other))),
+            };
+            let handle = |i: usize| fields[i].parse::<Handle>().map_err(|_| err(format!("invalid handle \"{}\"", fields[i])));
+            let arity = match fields[0] {
+                "ticks" | "steps" | "held" | "powered" => 2,
+                "clock" | "trace" => 3,
+                "switch" | "terminal" => 4,
+                other => return Err(err(format!("unknown record \"{}\"", other))),
+            };
+            if fields.len() != arity {
+                return Err(err(format!("\"{}\" takes {} fields, found {}", fields[0], arity - 1, fields.len() - 1)));
+            }
+            match fields[0] {
+                "ticks" => ret.ticks = number(1)?,
+                "steps" => ret.steps = number(1)?,
+                "clock" => {
+                    let phase = u8::try_from(number(2)?)
+                        .ok()
+                        .filter(|&phase| phase <= PHASES)
+                        .ok_or_else(|| err(format!("expected a phase from 0 to {}, found {}", PHASES, fields[2])))?;
+                    ret.clock = Some((number(1)?, phase));
+                }
+                "switch" => {
+                    let held_for = u32::try_from(number(3)?).map_err(|_| err(format!("{} ticks is too long to hold a switch", fields[3])))?;
+                    ret.switches.push((handle(1)?, bit(2)?, held_for));
+                }
+                "held" => ret.held.push(handle(1)?),
+                "powered" => ret.powered.push(handle(1)?),
+                "terminal" => {
+                    let occurrence = u32::try_from(number(2)?).map_err(|_| err(format!("no netlist has {} switches of one name", fields[2])))?;
+                    let terminal = match fields[3] {
+                        "pole" => Terminal::Pole,
+                        "no" => Terminal::No,
+                        "nc" => Terminal::Nc,
+                        other => return Err(err(format!("expected pole, no or nc, found \"{}\"", other))),
+                    };
+                    ret.powered_terminals.push((handle(1)?, occurrence, terminal));
+                }
+                "trace" => ret.traces.push((handle(1)?, bit(2)?)),
+                _ => unreachable!(),
+            }
+        }
+        Ok(ret)
+    }
+}
+
+impl Circuit {
+
+    /// Captures the current state; traced nodes without a label are left out
+    pub fn snapshot(&self) -> Snapshot {
+        let mut traces: Vec<(Handle, bool)> = self.traces
+            .iter()
+            .filter_map(|(&node_id, &powered)| self.directory.first_alias(node_id).map(|handle| (handle.clone(), powered)))
+            .collect();
+        traces.sort_by_key(|(handle, _)| handle.to_string());
+        let mut powered: Vec<Handle> = (0..self.num_nodes)
+            .filter(|&node_id| self.powered[node_id])
+            .filter_map(|node_id| self.directory.first_alias(node_id).cloned())
+            .collect();
+        powered.sort_by_key(Handle::to_string);
+        let occurrences = self.switch_occurrences();
+        let mut powered_terminals = Vec::new();
+        for (node_id, _) in self.powered.iter().enumerate().filter(|(_, &powered)| powered) {
+            if self.directory.first_alias(node_id).is_some() {
+                continue;
+            }
+            if let Some((switch, terminal)) = self.directory.first_terminal(node_id) {
+                powered_terminals.push((self.switches[switch].name.clone(), occurrences[switch], terminal));
+            }
+        }
+
+        Snapshot {
+            ticks: self.ticks,
+            steps: self.steps,
+            clock: self.clock.as_ref().map(|clock| (clock.cycle(), clock.phase())),
+            switches: self.switches
+                .iter()
+                .enumerate()
+                .map(|(id, switch)| (switch.name.clone(), self.switch_positions[id], self.armatures.held_for(id)))
+                .collect(),
+            held: self.held(),
+            powered,
+            powered_terminals,
+            traces,
+        }
+    }
+
+    /// Puts the circuit into the state captured by `snapshot`, discarding pending sources
+    ///
+    /// Nothing is changed unless every switch, held input and trace in the snapshot can be found
+    /// in this circuit and every switch of this circuit is covered by the snapshot.
+    pub fn restore(&mut self, snapshot: &Snapshot) -> Result<(), SnapshotError> {
+        let mismatch = |message: String| Err(SnapshotError::Mismatch(message));
+        if snapshot.clock.is_some() != self.clock.is_some() {
+            return mismatch(format!("snapshot {} a phase clock but the circuit {}",
+                                    if snapshot.clock.is_some() { "has" } else { "has no" },
+                                    if self.clock.is_some() { "does" } else { "does not" }));
+        }
+        if let Some((_, phase)) = snapshot.clock.filter(|&(_, phase)| phase > PHASES) {
+            return mismatch(format!("phase {} is past the last phase of the clock", phase));
+        }
+
+        let mut by_name: HashMap<&Handle, Vec<SwitchId>> = HashMap::new();
+        for (id, switch) in self.switches.iter().enumerate().rev() {
+            by_name.entry(&switch.name).or_default().push(id);
+        }
+        let mut switches = Vec::with_capacity(snapshot.switches.len());
+        for (name, position, held_for) in &snapshot.switches {
+            match by_name.get_mut(name).map(Vec::pop) {
+                Some(Some(id)) => switches.push((id, *position, *held_for)),
+                Some(None) => return mismatch(format!("snapshot has more {} switches than the circuit", name)),
+                None => return mismatch(format!("unknown switch {}", name)),
+            }
+        }
+        if let Some((name, _)) = by_name.iter().find(|(_, ids)| !ids.is_empty()) {
+            return mismatch(format!("switch {} is missing from the snapshot", name));
+        }
+
+        let mut held = HashMap::new();
+        for handle in &snapshot.held {
+            match self.labels.get(handle) {
+                Some(&node_id) => held.insert(handle.clone(), node_id),
+                None => return mismatch(format!("held input {} is not a label", handle)),
+            };
+        }
+        let mut powered = vec![false; self.num_nodes];
+        for handle in &snapshot.powered {
+            match self.labels.get(handle) {
+                Some(&node_id) => powered[node_id] = true,
+                None => return mismatch(format!("powered node {} is not a label", handle)),
+            }
+        }
+        let occurrences = self.switch_occurrences();
+        let by_occurrence: HashMap<(&Handle, u32), SwitchId> = self.switches
+            .iter()
+            .enumerate()
+            .map(|(id, switch)| ((&switch.name, occurrences[id]), id))
+            .collect();
+        for (name, occurrence, terminal) in &snapshot.powered_terminals {
+            let Some(&id) = by_occurrence.get(&(name, *occurrence)) else {
+                return mismatch(format!("powered terminal {} of switch {} #{} is not in the circuit", terminal, name, occurrence));
+            };
+            let switch = &self.switches[id];
+            powered[match terminal {
+                Terminal::Pole => switch.pole,
+                Terminal::No => switch.no,
+                Terminal::Nc => switch.nc,
+            }] = true;
+        }
+        let mut traces = Vec::with_capacity(snapshot.traces.len());
+        for (handle, powered) in &snapshot.traces {
+            match self.labels.get(handle) {
+                Some(node_id) if self.traces.contains_key(node_id) => traces.push((*node_id, *powered)),
+                _ => return mismatch(format!("{} is not traced", handle)),
+            }
+        }
+
+        for (id, position, held_for) in switches {
+            self.switch_positions.set(id, position);
+            self.armatures.set_held_for(id, held_for);
+        }
+        self.held = held;
+        self.held_changed = true;
+        self.powered = powered;
+        for (node_id, powered) in traces {
+            self.traces.insert(node_id, powered);
+            self.powered[node_id] = powered;
+        }
+        self.ticks = snapshot.ticks;
+        self.steps = snapshot.steps;
+        if let (Some(clock), Some((cycle, phase))) = (&mut self.clock, snapshot.clock) {
+            clock.jump_to(cycle, phase);
+        }
+        self.sources.clear();
+        self.event.invalidate();
+        if let Some(detector) = &mut self.cycles {
+            detector.restart(&self.switch_positions);
+        }
+        self.resync_observers();
+        self.reset_history();
+        Ok(())
+    }
+
+    /// SwitchId -> number of switches before it in the netlist that share its name
+    fn switch_occurrences(&self) -> Vec<u32> {
+        let mut seen: HashMap<&Handle, u32> = HashMap::new();
+        self.switches
+            .iter()
+            .map(|switch| {
+                let count = seen.entry(&switch.name).or_default();
+                *count += 1;
+                *count - 1
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::circuit::{CircuitBuilder, SubcircuitBuilder, Timing};
+
+    fn delay_line() -> Circuit {
+        CircuitBuilder::new()
+            .with_phase_clock()
+            .add_subcircuit(|mut scb| {
+                let input = scb.label("In");
+                let g = scb.label("G");
+                SubcircuitBuilder::chain(input, 0..4, |coil_pos, i| {
+                    scb.coil_with_timing(handle!("Aa", i), coil_pos, Timing::new(2, 1 + i as u32));
+                    let out = scb.label(handle!("out", i));
+                    scb.trace(out);
+                    scb.add_switch(handle!("aa", i), (g, out, None));
+                    out
+                });
+            })
+            .finalize()
+    }
+
+    #[test]
+    fn restored_circuit_continues_identically() {
+        let mut original = delay_line();
+        original.hold(&handle!("In"));
+        for _ in 0..3 {
+            original.step();
+        }
+        original.release(&handle!("In"));
+        original.step();
+
+        let text = original.snapshot().to_string();
+        let snapshot: Snapshot = text.parse().unwrap();
+        assert_eq!(snapshot, original.snapshot());
+        let mut restored = delay_line();
+        restored.record_history(1 << 20);
+        restored.restore(&snapshot).unwrap();
+        assert_eq!(restored.snapshot(), snapshot);
+        assert_eq!(restored.history_range(), Some(4..=4));
+        for handle in [handle!("In"), handle!("G"), handle!("Aa", 1)] {
+            assert_eq!(restored.inspect(&handle), original.inspect(&handle), "{} differs", handle);
+        }
+
+        for _ in 0..8 {
+            original.step();
+            restored.step();
+            assert_eq!((restored.steps(), restored.ticks()), (original.steps(), original.ticks()));
+            assert_eq!(restored.switch_positions, original.switch_positions);
+            for i in 0..4 {
+                assert_eq!(restored.inspect(&handle!("out", i)), original.inspect(&handle!("out", i)));
+            }
+        }
+        assert!(restored.step_back());
+        assert_eq!(restored.steps(), 11);
+    }
+
+    #[test]
+    fn restores_unlabelled_powered_nodes() {
+        let build = || CircuitBuilder::new()
+            .add_subcircuit(|mut scb| {
+                let (g, input) = (scb.label("G"), scb.label("In"));
+                scb.coil(handle!("Aa", 0), input);
+                let (_, mid, _) = scb.add_switch(handle!("aa", 0), (g, None, None));
+                scb.add_switch(handle!("aa", 0), (mid, None, None));
+            })
+            .finalize();
+        let mut original = build();
+        original.hold(&handle!("In"));
+        original.step();
+        original.step();
+        let snapshot = original.snapshot();
+        assert_eq!(snapshot.powered_terminals, vec![
+            (handle!("aa", 0), 0, Terminal::No),
+            (handle!("aa", 0), 1, Terminal::No),
+        ]);
+        assert_eq!(snapshot.to_string().parse::<Snapshot>().unwrap(), snapshot);
+
+        let mut restored = build();
+        restored.restore(&snapshot).unwrap();
+        assert_eq!(restored.powered, original.powered);
+    }
+
+    #[test]
+    fn rejects_other_netlists_and_versions() {
+        let snapshot = delay_line().snapshot();
+        let mut other = CircuitBuilder::new()
+            .with_phase_clock()
+            .add_subcircuit(|mut scb| {
+                let g = scb.label("G");
+                scb.coil(handle!("Aa", 0), g);
+                scb.add_switch(handle!("aa", 0), (g, None, None));
+            })
+            .finalize();
+        let before = other.snapshot();
+        assert!(matches!(other.restore(&snapshot), Err(SnapshotError::Mismatch(_))));
+        assert_eq!(other.snapshot(), before);
+
+        assert!(matches!("z3mu snapshot 1\nticks 0\n".parse::<Snapshot>(), Err(SnapshotError::File(FileError::Version { found: 1, supported: 3 }))));
+        assert_eq!("ticks 0\n".parse::<Snapshot>().unwrap_err().to_string(), "snapshot: line 1: expected a \"z3mu snapshot <version>\" header");
+        assert!(matches!("z3mu snapshot 3\nswitch aa_0 2 0\n".parse::<Snapshot>(), Err(SnapshotError::File(FileError::Parse { line: 2, .. }))));
+        assert!(matches!("z3mu snapshot 3\nticks 0\nclock 3 6\n".parse::<Snapshot>(), Err(SnapshotError::File(FileError::Parse { line: 3, .. }))));
+        assert!(matches!("z3mu snapshot 3\nclock 3 261\n".parse::<Snapshot>(), Err(SnapshotError::File(FileError::Parse { line: 2, .. }))));
+        assert!(matches!("z3mu snapshot 3\nswitch aa_0 1 4294967296\n".parse::<Snapshot>(), Err(SnapshotError::File(FileError::Parse { line: 2, .. }))));
+        let mut clocked = other.snapshot();
+        clocked.clock = Some((0, 7));
+        assert!(matches!(other.restore(&clocked), Err(SnapshotError::Mismatch(_))));
+    }
+}
@@ -1,35 +1,49 @@
-use std::collections::HashMap;
-use std::iter::zip;
+use std::collections::{HashMap, HashSet};
 use log::*;
 
-use super::{Circuit, Coil, Switch, Handle, NodeId};
+use super::{Circuit, Switch, Handle, NodeId, Engine, PhaseClock, Rail, SessionEntry, Severity, Timing, Z3muError};
+use super::directory::NodeDirectory;
+use super::scope::Scope;
+use super::electrical::{Electrical, Load};
+use super::timing::Armatures;
+use super::engine::TwoPhaseState;
+use super::topology::{BitSet, Contact, Csr};
 
 type SwitchId = usize;
 
 #[derive(Default)]
 pub struct CircuitBuilder {
-    num_nodes: usize,
-    switches: Vec<BuilderSwitch>,
-    coils: HashMap<Handle, NodeId>,
-    labels: HashMap<Handle, NodeId>,
+    pub(super) num_nodes: usize,
+    pub(super) switches: Vec<BuilderSwitch>,
+    pub(super) coils: HashMap<Handle, NodeId>,
+    pub(super) coil_order: Vec<Handle>, // coils and two-terminal coils in the order they were added
+    timings: HashMap<Handle, Timing>, // coil handle -> Timing, for coils with non-default timing
+    pub(super) labels: HashMap<Handle, NodeId>,
     traces: Vec<NodeId>,
+    ticks_per_step: u32,
+    phase_clock: bool,
+    electrical: bool,
+    pub(super) ties: Vec<(NodeId, Rail)>,
+    pub(super) loads: Vec<(Handle, NodeId, NodeId)>, // two-terminal coils as (handle, pos, neg)
+    pub(super) subcircuits: Vec<String>, // names of the subcircuits added so far
+    pub(super) scopes: HashSet<String>, // paths of the scopes built so far
+    pub(super) mismatched_port: Option<String>, // first port an instance used with an undeclared index
+    node_owners: Vec<Option<usize>>, // NodeId -> subcircuit that created the node
+    pub(super) label_uses: HashMap<Handle, u32>, // times each label or coil was mentioned while building
+    pub(super) strict: bool,
 }
 
 /// A subcircuit in the process of being built
 pub struct SubcircuitBuilder<'a> {
-    cb: &'a mut CircuitBuilder,
+    pub(super) cb: &'a mut CircuitBuilder,
+    pub(super) scope: Option<Scope>, // None for subcircuits whose labels are all global
 }
 
-struct BuilderSwitch {
-    name: Handle,
-    pole: NodeId,
-    no: NodeId,
-    nc: NodeId,
-}
-
-struct BuilderCoil {
-    name: Handle,
-    pos: NodeId,
+pub(super) struct BuilderSwitch {
+    pub(super) name: Handle,
+    pub(super) pole: NodeId,
+    pub(super) no: NodeId,
+    pub(super) nc: NodeId,
 }
 
 impl CircuitBuilder {
@@ -39,17 +53,74 @@ impl CircuitBuilder {
         // boostrap with G node
         ret.labels.insert(handle!("G"), 0);
         ret.num_nodes += 1;
+        ret.node_owners.push(None);
+        ret.ticks_per_step = 1;
 
         ret
     }
 
-    pub fn add_subcircuit<F: FnOnce(SubcircuitBuilder)>(mut self, build: F) -> Self {
-        let scb = SubcircuitBuilder { cb: &mut self };
+    /// Sets how many ticks of the relay clock make up one `Circuit::step`
+    pub fn ticks_per_step(mut self, ticks: u32) -> Self {
+        assert!(ticks > 0);
+        self.ticks_per_step = ticks;
+        self
+    }
+
+    /// Attaches a `PhaseClock` that drives the `S` labels, one phase per step
+    pub fn with_phase_clock(mut self) -> Self {
+        self.phase_clock = true;
+        self
+    }
+
+    /// Switches to the two-rail electrical model
+    ///
+    /// `G` is tied to the positive rail, nodes may be tied to either rail with
+    /// `SubcircuitBuilder::tie`, and coils added with `SubcircuitBuilder::coil_between` only pick
+    /// up when current can flow from their positive to their negative terminal. Any tick in which
+    /// closed contacts join the rails is reported as a `ShortCircuit`. Circuits in electrical mode
+    /// always step with `Engine::TwoPhase`.
+    ///
+    /// Coils added with `SubcircuitBuilder::coil` keep the implicit return, so they still pick up
+    /// whenever their node is powered, and coils added with `coil_between` take no `Timing`: they
+    /// move at the end of the tick in which current flows through them.
+    pub fn electrical(mut self) -> Self {
+        self.electrical = true;
+        self
+    }
+
+    pub fn add_subcircuit<F: FnOnce(SubcircuitBuilder)>(self, build: F) -> Self {
+        let name = format!("subcircuit {}", self.subcircuits.len());
+        self.add_named_subcircuit(name, build)
+    }
+
+    /// Adds a subcircuit under a name that node diagnostics report as the creator of its nodes
+    pub fn add_named_subcircuit<F: FnOnce(SubcircuitBuilder)>(mut self, name: impl Into<String>, build: F) -> Self {
+        self.subcircuits.push(name.into());
+        let scb = SubcircuitBuilder { cb: &mut self, scope: None };
         build(scb);
         self
     }
 
     pub fn finalize(self) -> Circuit {
+        self.try_finalize().unwrap_or_else(|err| panic!("{}", err))
+    }
+
+    /// Like `finalize`, but hands back the validation report as `Z3muError::Validation` instead
+    /// of panicking when a `strict` builder's netlist has errors
+    ///
+    /// Coils of one relay with different timings are always an error.
+    pub fn try_finalize(self) -> Result<Circuit, Z3muError> {
+        let report = self.validate();
+        if self.strict && report.has_errors() {
+            return Err(Z3muError::Validation(report));
+        }
+        for issue in &report.issues {
+            match issue.severity() {
+                Severity::Info => debug!("{}", issue),
+                Severity::Warning | Severity::Error => warn!("{}", issue),
+            }
+        }
+
         // initialize switches and connections
         let mut switches_by_name: HashMap<Handle, Vec<SwitchId>> = HashMap::new();
         let mut switches = Vec::<Switch>::new();
@@ -63,43 +134,96 @@ impl CircuitBuilder {
         }
 
         // initialize coils
-        let mut coils = Vec::<Vec<Coil>>::new();
-        coils.reserve(self.num_nodes);
-        for _ in 0..self.num_nodes{
-            coils.push(Vec::new());
-        }
-        for (coil_handle, coil_pos) in self.coils {
+        let mut coils = Vec::<(NodeId, SwitchId)>::new();
+        let mut timings: Vec<Option<Timing>> = vec![None; switches.len()];
+        let mut coil_nodes: Vec<(Handle, NodeId)> = self.coils.iter().map(|(handle, node_id)| (handle.clone(), *node_id)).collect();
+        coil_nodes.sort_by_key(|(handle, _)| handle.to_string());
+        for coil_handle in &self.coil_order {
+            let Some(&coil_pos) = self.coils.get(coil_handle) else {
+                continue; // two-terminal coil
+            };
             let switches = switches_by_name
-                .get(&CircuitBuilder::coil_to_switch_name(&coil_handle))
+                .get(&CircuitBuilder::coil_to_switch_name(coil_handle))
                 .map_or_else(Vec::new, Vec::clone);
-
-            if switches.is_empty() {
-                warn!("Coil {} is not connected to any switches", coil_handle);
+            if let Some(&timing) = self.timings.get(coil_handle) {
+                for &switch in &switches {
+                    // coils of one relay, e.g. `Ba_-3` and `Ba_-3^1`, must agree on its timing
+                    if let Some(existing) = timings[switch].replace(timing).filter(|&existing| existing != timing) {
+                        let subcircuit = self.node_owners[coil_pos].map(|owner| self.subcircuits[owner].clone()).unwrap_or_default();
+                        return Err(Z3muError::ConflictingTiming { coil: coil_handle.clone(), timing, existing, subcircuit });
+                    }
+                }
             }
 
-            coils[coil_pos].push(Coil { switches });
+            coils.extend(switches.into_iter().map(|switch| (coil_pos, switch)));
         }
+        assert!(self.electrical || (self.ties.is_empty() && self.loads.is_empty()),
+                "Rails and two-terminal coils need CircuitBuilder::electrical");
+        let electrical = self.electrical.then(|| {
+            let loads = self.loads.into_iter().map(|(handle, pos, neg)| {
+                let switches = switches_by_name
+                    .get(&CircuitBuilder::coil_to_switch_name(&handle))
+                    .map_or_else(Vec::new, Vec::clone);
+                Load { handle, pos, neg, switches }
+            }).collect();
+            Electrical::new(self.num_nodes, self.ties, loads)
+        });
+        let contacts = switches.iter().enumerate().flat_map(|(id, switch)| [
+            (switch.pole, Contact { switch: id, other: switch.no, closed_when: true }),
+            (switch.no, Contact { switch: id, other: switch.pole, closed_when: true }),
+            (switch.pole, Contact { switch: id, other: switch.nc, closed_when: false }),
+            (switch.nc, Contact { switch: id, other: switch.pole, closed_when: false }),
+        ]).collect();
         let traces: HashMap<NodeId, bool> = self.traces.into_iter().map(|node_id| (node_id, false)).collect();
+        let clock = self.phase_clock.then(|| PhaseClock::new(self.labels.iter()));
+        let directory = NodeDirectory::new(&self.labels, &switches, &coil_nodes, self.node_owners, self.subcircuits);
 
         let mut ret = Circuit {
             num_nodes: self.num_nodes,
-            coils,
+            coils: Csr::from_pairs(self.num_nodes, coils),
+            contacts: Csr::from_pairs(self.num_nodes, contacts),
+            switch_positions: BitSet::new(switches.len()),
+            last_positions: BitSet::new(switches.len()),
+            armatures: Armatures::new(timings.into_iter().map(Option::unwrap_or_default).collect()),
+            two_phase: TwoPhaseState::new(self.num_nodes, switches.len()),
             switches,
             labels: self.labels,
+            coil_nodes,
+            directory,
             traces,
             sources: Vec::new(),
-
-            switch_positions: Vec::new(),
-            connections: Vec::new(),
+            held: HashMap::new(),
+            held_changed: false,
+            step_sources: Vec::new(),
+            ticks_per_step: self.ticks_per_step,
+
+            powered: vec![false; self.num_nodes],
+            last_sources: Vec::new(),
+            ticks: 0,
+            steps: 0,
             initialized: false,
+            engine: Engine::default(),
+            event: Default::default(),
+            cycles: None,
+            clock,
+            electrical,
+            history: None,
+            recording: None,
+            observers: Default::default(),
         };
-        ret.step(); // initialize connections and switch_positions
+        ret.run_tick(); // initialize switch_positions
         ret.initialized = true;
-        ret
+        Ok(ret)
     }
 
+    /// Relay operated by a coil, e.g. `ba_-3` for `Ba_-3^1`; only the last segment of a scoped
+    /// name is lowercased
     pub fn coil_to_switch_name(coil_handle: &Handle) -> Handle {
-        Handle::new(coil_handle.name.to_lowercase(), coil_handle.index, None)
+        let name = match coil_handle.name.rsplit_once('/') {
+            Some((path, name)) => format!("{}/{}", path, name.to_lowercase()),
+            None => coil_handle.name.to_lowercase(),
+        };
+        Handle::new(name, coil_handle.index, None)
     }
 }
 
@@ -108,11 +232,13 @@ impl<'a> SubcircuitBuilder<'a> {
     fn new_node(&mut self) -> NodeId {
         let new_node = self.cb.num_nodes;
         self.cb.num_nodes += 1;
-        return new_node;
+        self.cb.node_owners.push(Some(self.cb.subcircuits.len() - 1));
+        new_node
     }
 
     pub fn label(&mut self, label: impl Into<Handle>) -> NodeId {
-        let handle: Handle = label.into();
+        let handle = self.qualify_use(label.into());
+        *self.cb.label_uses.entry(handle.clone()).or_default() += 1;
         if let Some(existing) = self.cb.labels.get(&handle) {
             *existing
         } else {
@@ -157,25 +283,86 @@ impl<'a> SubcircuitBuilder<'a> {
     /// assert_eq!(n0, n2);
     /// ```
     pub fn coil(&mut self, handle: impl Into<Handle>, pos: impl Into<Option<NodeId>>) -> NodeId {
-        let handle = handle.into();
-        if self.cb.labels.contains_key(&handle) {
-            assert_eq!(pos.into(), None);
-            self.cb.labels[&handle]
+        self.try_coil(handle, pos).unwrap_or_else(|err| panic!("{}", err))
+    }
+
+    /// Adds a coil like `coil` whose relay picks up and drops out after the given delays
+    pub fn coil_with_timing(&mut self, handle: impl Into<Handle>, pos: impl Into<Option<NodeId>>, timing: Timing) -> NodeId {
+        self.try_coil_with_timing(handle, pos, timing).unwrap_or_else(|err| panic!("{}", err))
+    }
+
+    /// Like `coil`, but reports a coil that is already on another node instead of panicking
+    pub fn try_coil(&mut self, handle: impl Into<Handle>, pos: impl Into<Option<NodeId>>) -> Result<NodeId, Z3muError> {
+        self.add_coil(handle.into(), pos.into(), None)
+    }
+
+    /// Like `coil_with_timing`, but also reports a coil that already has a different timing
+    pub fn try_coil_with_timing(&mut self, handle: impl Into<Handle>, pos: impl Into<Option<NodeId>>, timing: Timing) -> Result<NodeId, Z3muError> {
+        self.add_coil(handle.into(), pos.into(), Some(timing))
+    }
+
+    /// Adds a coil or looks up an existing one; `timing` is None where the caller does not care
+    fn add_coil(&mut self, handle: Handle, pos: Option<NodeId>, timing: Option<Timing>) -> Result<NodeId, Z3muError> {
+        let handle = self.qualify_use(handle);
+        *self.cb.label_uses.entry(handle.clone()).or_default() += 1;
+        if let Some(&node) = self.cb.labels.get(&handle) {
+            let subcircuit = self.cb.subcircuits.last().cloned().unwrap_or_default();
+            if pos.is_some() {
+                return Err(Z3muError::DuplicateCoil { coil: handle, node, subcircuit });
+            }
+            let existing = self.cb.timings.get(&handle).copied().unwrap_or_default();
+            if let Some(timing) = timing.filter(|&timing| timing != existing) {
+                return Err(Z3muError::ConflictingTiming { coil: handle, timing, existing, subcircuit });
+            }
+            Ok(node)
         } else {
-            let pos = self.node(pos.into());
-            let prev = self.cb.coils.insert(handle.clone(), pos);
-            assert_eq!(prev, None);
-            let prev = self.cb.labels.insert(handle, pos);
-            assert_eq!(prev, None);
-            pos
+            let pos = self.node(pos);
+            if let Some(timing) = timing.filter(|&timing| timing != Timing::default()) {
+                self.cb.timings.insert(handle.clone(), timing);
+            }
+            // not a label yet, so not a coil either
+            self.cb.coils.insert(handle.clone(), pos);
+            self.cb.coil_order.push(handle.clone());
+            self.cb.labels.insert(handle, pos);
+            Ok(pos)
+        }
+    }
+
+    /// Ties a node to a supply rail; only allowed in electrical mode
+    pub fn tie(&mut self, node: NodeId, rail: Rail) {
+        self.cb.ties.push((node, rail));
+    }
+
+    /// Adds a coil whose negative terminal is a node of the circuit rather than the implicit
+    /// return; only allowed in electrical mode
+    ///
+    /// Returns the positive and negative terminals. The handle is an alias for the positive one.
+    pub fn coil_between(&mut self, handle: impl Into<Handle>, pos: impl Into<Option<NodeId>>, neg: impl Into<Option<NodeId>>) -> (NodeId, NodeId) {
+        self.try_coil_between(handle, pos, neg).unwrap_or_else(|err| panic!("{}", err))
+    }
+
+    /// Like `coil_between`, but reports a handle that already labels a node instead of panicking
+    pub fn try_coil_between(&mut self, handle: impl Into<Handle>, pos: impl Into<Option<NodeId>>, neg: impl Into<Option<NodeId>>)
+        -> Result<(NodeId, NodeId), Z3muError> {
+        let handle = self.qualify_use(handle.into());
+        if let Some(&node) = self.cb.labels.get(&handle) {
+            let subcircuit = self.cb.subcircuits.last().cloned().unwrap_or_default();
+            return Err(Z3muError::DuplicateCoil { coil: handle, node, subcircuit });
         }
+        let pos = self.node(pos.into());
+        let neg = self.node(neg.into());
+        self.cb.labels.insert(handle.clone(), pos);
+        self.cb.coil_order.push(handle.clone());
+        self.cb.loads.push((handle, pos, neg));
+        Ok((pos, neg))
     }
 
     pub fn add_switch(&mut self, name: impl Into<Handle>, loc: (impl Into<Option<NodeId>>, impl Into<Option<NodeId>>, impl Into<Option<NodeId>>)) -> (NodeId, NodeId, NodeId) {
         let pole = self.node(loc.0.into());
         let no = self.node(loc.1.into());
         let nc = self.node(loc.2.into());
-        self.cb.switches.push(BuilderSwitch { name: name.into(), pole, no, nc });
+        let name = self.qualify_switch(name.into());
+        self.cb.switches.push(BuilderSwitch { name, pole, no, nc });
         (pole, no, nc)
     }
 
@@ -193,68 +380,158 @@ impl<'a> SubcircuitBuilder<'a> {
 
 impl Circuit {
 
-    /// Signal that a node has been pulled high and propagate effects through the circuit
-    ///
-    /// # Arguments
-    ///
-    /// * `node_name` - Alias for the node being pulled high
+    /// Propagate power from `G` and the pending sources and return the next switch positions
     pub fn step_a(&mut self) -> Vec<bool> {
-        let mut visited = vec![false; self.num_nodes];
-        
-        let mut next_switch_positions = vec![false; self.switches.len()];
+        self.flood();
+        let next_positions = &self.two_phase.next_positions;
+        (0..self.switches.len()).map(|switch| next_positions.get(switch)).collect()
+    }
+
+    /// Move every switch towards the coil state returned by `step_a`
+    pub fn step_b(&mut self, next_switch_positions: Vec<bool>) {
+        let next_positions = &mut self.two_phase.next_positions;
+        for (switch, energized) in next_switch_positions.into_iter().enumerate() {
+            next_positions.set(switch, energized);
+        }
+        self.move_switches();
+    }
+
+    /// `step_a` without building the returned vector; the next positions are left in the
+    /// two-phase scratch space
+    ///
+    /// Powered regions are the union-find components of the closed contacts that contain a source.
+    pub(super) fn flood(&mut self) {
+        let scratch = &mut self.two_phase;
+        scratch.next_positions.clear();
         if self.initialized {
-            self.sources.push(self.labels[&handle!("G")]);
-            while let Some(node) = self.sources.pop() {
-                if visited[node] {
-                    continue;
-                }
-                visited[node] = true;
+            let components = &mut scratch.components;
+            components.reset();
+            for (id, switch) in self.switches.iter().enumerate() {
+                let branch = if self.switch_positions.get(id) {
+                    switch.no
+                } else {
+                    switch.nc
+                };
+                components.union(switch.pole, branch);
+            }
 
-                for coil in &self.coils[node] {
-                    for switch in &coil.switches {
-                        next_switch_positions[*switch] = true;
-                    }
-                }
-                for other in &self.connections[node] {
-                    if !visited[*other] {
-                        self.sources.push(*other);
+            scratch.root_powered.fill(false);
+            self.sources.push(self.labels[&handle!("G")]);
+            for &source in &self.sources {
+                scratch.root_powered[components.find(source)] = true;
+            }
+            for node in 0..self.num_nodes {
+                let powered = scratch.root_powered[components.find(node)];
+                self.powered[node] = powered;
+                if powered {
+                    for &switch in self.coils.row(node) {
+                        scratch.next_positions.set(switch, true);
                     }
                 }
             }
             for (node_id, b) in &mut self.traces {
-                *b = visited[*node_id];
+                *b = self.powered[*node_id];
             }
+            self.step_electrical();
+            self.sources.clear();
         }
-        next_switch_positions
     }
 
-    pub fn step_b(&mut self, mut next_switch_positions: Vec<bool>) {
-        std::mem::swap(&mut self.switch_positions, &mut next_switch_positions);
-        self.connections = vec![Vec::new(); self.num_nodes];
-        for (active, switch) in zip(&self.switch_positions, &self.switches) {
-            let branch = if *active {
-                switch.no
-            } else {
-                switch.nc
-            };
-            Circuit::connect(&mut self.connections, switch.pole, branch);
+    /// `step_b` for the next positions left behind by `flood`
+    pub(super) fn move_switches(&mut self) {
+        for switch in 0..self.switches.len() {
+            let energized = self.two_phase.next_positions.get(switch);
+            let position = self.armatures.advance(switch, energized, self.switch_positions.get(switch));
+            self.switch_positions.set(switch, position);
         }
+        self.event.invalidate();
+    }
+
+    /// Advance the relay clock by one tick, consuming the pending sources
+    ///
+    /// Held inputs and, with a phase clock attached, the lines of the current phase are high as well.
+    pub fn tick(&mut self) {
+        self.run_tick();
+        self.record_entry(|c| SessionEntry::Tick(c.traced()));
     }
 
+    /// Like `tick`, without logging it to a recording session, for the ticks of a step
+    pub(super) fn run_tick(&mut self) {
+        // the clock and inputs that stay held are not arriving inputs, but anything set is
+        let quiet = self.sources.is_empty() && !self.held_changed;
+        self.held_changed = false;
+        self.sources.extend(self.held.values());
+        if let Some(clock) = &self.clock {
+            self.sources.extend_from_slice(clock.lines());
+        }
+        if let Some(el) = &self.electrical {
+            self.sources.extend_from_slice(el.positive());
+        }
+        self.last_positions.clone_from(&self.switch_positions);
+        self.last_sources.clone_from(&self.sources);
+        match self.engine {
+            // the event engine knows nothing of rails, so electrical circuits always use two phases
+            Engine::EventDriven if self.electrical.is_none() => self.step_event(),
+            _ => {
+                self.flood();
+                self.move_switches();
+            }
+        }
+        if !self.initialized {
+            return;
+        }
+        self.ticks += 1;
+        if let Some(detector) = &mut self.cycles {
+            let phase = self.clock.as_ref().map_or(0, PhaseClock::phase);
+            detector.observe(quiet, self.ticks, (&self.switch_positions, &self.armatures, phase), &self.switches);
+        }
+        self.notify_tick();
+    }
+
+    /// Advance by `ticks_per_step` ticks, holding the pending sources high for all of them
+    ///
+    /// With a phase clock attached, the clock moves on to its next phase first.
     pub fn step(&mut self) {
-        let next_switch_positions = self.step_a();
-        self.step_b(next_switch_positions);
+        self.observers.step_started(self.steps + 1);
+        if let Some(clock) = &mut self.clock {
+            clock.advance();
+        }
+        let mut step_sources = std::mem::take(&mut self.step_sources);
+        step_sources.clear();
+        step_sources.extend_from_slice(&self.sources);
+        for tick in 0..self.ticks_per_step {
+            if tick > 0 {
+                self.sources.extend_from_slice(&step_sources);
+            }
+            self.run_tick();
+        }
+        self.step_sources = step_sources;
+        self.end_step(|c| SessionEntry::Step(c.traced()));
+    }
+
+    /// Counts a step that `step` or `settle` has just ticked through, recording it in the
+    /// history and the session and telling the observers that it is over
+    pub(super) fn end_step(&mut self, entry: impl FnOnce(&Circuit) -> SessionEntry) {
+        self.steps += 1;
+        self.record_step();
+        self.record_entry(entry);
+        self.observers.step_ended(self.steps);
     }
 
-    fn connect(connections: &mut Vec<Vec<NodeId>>, a: NodeId, b: NodeId) {
-        connections[a].push(b);
-        connections[b].push(a);
+    pub fn ticks_per_step(&self) -> u32 {
+        self.ticks_per_step
+    }
+
+    /// Number of ticks simulated since the circuit was finalized
+    pub fn ticks(&self) -> u64 {
+        self.ticks
     }
 }
 
 impl From<BuilderSwitch> for Switch {
     fn from(bs: BuilderSwitch) -> Switch {
         Switch {
+            name: bs.name,
             pole: bs.pole,
             no: bs.no,
             nc: bs.nc,
@@ -309,6 +586,7 @@ mod tests {
     }
 
     #[test]
+    #[allow(clippy::needless_range_loop)]
     fn step_subcircuit() {
         let mut step = [0usize; 6]; // step[0] is unused for simplicity
         let mut step123 = 0usize;
@@ -394,4 +672,137 @@ mod tests {
         }
     }
 
+    fn one_relay_fixture(scb: &mut SubcircuitBuilder) -> (NodeId, NodeId) {
+        let g = scb.label("G");
+        scb.coil(handle!("Ab", 0), g);
+        let (_, no, nc) = scb.add_switch("ab_0", (g, None, None));
+        scb.trace_all([no, nc]);
+        (no, nc)
+    }
+
+    fn oscillating_relay_fixture(scb: &mut SubcircuitBuilder) -> NodeId {
+        let g = scb.label("G");
+        let (_, _, coil_node) = scb.add_switch("xy_-10", (g, None, None));
+        scb.coil("Xy_-10", Some(coil_node));
+        scb.trace(coil_node);
+        coil_node
+    }
+
+    fn step_fixture(scb: &mut SubcircuitBuilder) -> ([NodeId; 6], NodeId) {
+        let mut step = [0usize; 6]; // step[0] is unused for simplicity
+        let g = scb.label("G");
+        let step123 = scb.label("step123");
+        scb.trace(step123);
+        scb.coil("Init", g);
+        for (i, node) in step.iter_mut().enumerate().skip(1) {
+            *node = scb.coil(handle!("S", i as i8), None);
+            scb.trace(*node);
+        }
+        scb.add_switch("init", (g, None, step[1]));
+        scb.add_switch(handle!("s", 1), (g, step[2], None));
+        scb.add_switch(handle!("s", 2), (g, step[3], None));
+        scb.add_switch(handle!("s", 3), (g, step[4], None));
+        scb.add_switch(handle!("s", 4), (g, step[5], None));
+        scb.add_switch(handle!("s", 5), (g, step[1], None));
+
+        scb.add_switch("init", (g, None, step123));
+        scb.add_switch(handle!("s", 5), (g, step123, None));
+        scb.add_switch(handle!("s", 1), (g, step123, None));
+        scb.add_switch(handle!("s", 2), (g, step123, None));
+        (step, step123)
+    }
+
+    fn alternating_relays_fixture(scb: &mut SubcircuitBuilder) {
+        let g = scb.label("G");
+        let (last_a, last_b) = SubcircuitBuilder::chain((g, g), 0..5, |(left_a, left_b), i| {
+            scb.coil(handle!("Bb", i), Some(left_a));
+            let right_a = scb.label(handle!("a", i));
+            let right_b = scb.label(handle!("b", i));
+            scb.trace_all([right_a, right_b]);
+            scb.add_switch(
+                handle!("aa", i),
+                (Some(left_a), Some(right_a), None));
+            scb.add_switch(
+                handle!("bb", i),
+                (Some(left_b), Some(right_b), None));
+            scb.coil(handle!("Aa", i), Some(right_b));
+            (right_a, right_b)
+        });
+        assert_eq!(last_a, scb.label("a_4"));
+        assert_eq!(last_b, scb.label("b_4"));
+    }
+
+    /// Steps a two-phase and an event-driven copy of a circuit side by side, pulling the given
+    /// labels high before each step, and checks that they never diverge
+    fn assert_engines_agree(build: impl Fn() -> Circuit, inputs: &[&[&str]]) {
+        let mut reference = build();
+        reference.set_engine(Engine::TwoPhase);
+        let mut c = build();
+        c.set_engine(Engine::EventDriven);
+        for (i, labels) in inputs.iter().enumerate() {
+            for label in labels.iter() {
+                reference.set(&Handle::from(*label));
+                c.set(&Handle::from(*label));
+            }
+            reference.step();
+            c.step();
+            assert_eq!(reference.traces, c.traces, "traces diverged at step {}", i);
+            assert_eq!(reference.switch_positions, c.switch_positions, "switches diverged at step {}", i);
+        }
+    }
+
+    #[test]
+    fn engines_agree() {
+        let idle: &[&[&str]] = &[&[] as &[&str]; 16];
+        assert_engines_agree(|| CircuitBuilder::new()
+            .add_subcircuit(|mut scb| { one_relay_fixture(&mut scb); })
+            .finalize(), idle);
+        assert_engines_agree(|| CircuitBuilder::new()
+            .add_subcircuit(|mut scb| { oscillating_relay_fixture(&mut scb); })
+            .finalize(), idle);
+        assert_engines_agree(|| CircuitBuilder::new()
+            .add_subcircuit(|mut scb| { step_fixture(&mut scb); })
+            .finalize(), idle);
+        assert_engines_agree(|| CircuitBuilder::new()
+            .add_subcircuit(|mut scb| alternating_relays_fixture(&mut scb))
+            .finalize(), idle);
+        assert_engines_agree(|| CircuitBuilder::new()
+            .add_subcircuit(|mut scb| alternating_relays_fixture(&mut scb))
+            .finalize(), &[&["a_0"], &[], &["b_2", "a_3"], &["b_2"], &[], &[], &["a_1"], &[], &[]]);
+    }
+
+    #[test]
+    fn step_a_then_step_b_matches_step() {
+        let build = || CircuitBuilder::new()
+            .add_subcircuit(|mut scb| alternating_relays_fixture(&mut scb))
+            .finalize();
+        let mut reference = build();
+        let mut c = build();
+        for _ in 0..12 {
+            reference.step();
+            let next_switch_positions = c.step_a();
+            assert_eq!(next_switch_positions.len(), c.switches.len());
+            c.step_b(next_switch_positions);
+            assert_eq!(reference.traces, c.traces);
+            assert_eq!(reference.switch_positions, c.switch_positions);
+        }
+    }
+
+    #[test]
+    fn switching_engines_mid_run() {
+        let build = || CircuitBuilder::new()
+            .add_subcircuit(|mut scb| alternating_relays_fixture(&mut scb))
+            .finalize();
+        let mut reference = build();
+        reference.set_engine(Engine::TwoPhase);
+        let mut c = build();
+        for i in 0..12 {
+            c.set_engine(if i % 3 == 0 { Engine::TwoPhase } else { Engine::EventDriven });
+            reference.step();
+            c.step();
+            assert_eq!(reference.traces, c.traces);
+            assert_eq!(reference.switch_positions, c.switch_positions);
+        }
+    }
+
 }
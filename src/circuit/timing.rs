@@ -0,0 +1,193 @@
+use super::SwitchId;
+
+/// Pickup and dropout delays of a relay, in ticks
+///
+/// A relay's contacts only move once its coil has been energized (or de-energized) for the whole
+/// delay. The default of one tick each way moves the contacts on the tick after the coil changes.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct Timing {
+    pickup: u32,
+    dropout: u32,
+}
+
+impl Timing {
+    pub fn new(pickup: u32, dropout: u32) -> Self {
+        assert!(pickup > 0 && dropout > 0, "relay delays must be at least one tick");
+        Timing { pickup, dropout }
+    }
+
+    pub fn pickup(&self) -> u32 {
+        self.pickup
+    }
+
+    pub fn dropout(&self) -> u32 {
+        self.dropout
+    }
+}
+
+impl Default for Timing {
+    fn default() -> Self {
+        Timing::new(1, 1)
+    }
+}
+
+/// Armature state of every switch, tracking how long its coil has disagreed with its position
+#[derive(Default)]
+pub(super) struct Armatures {
+    timings: Vec<Timing>, // SwitchId -> Timing
+    held_for: Vec<u32>, // SwitchId -> ticks the coil has disagreed with the switch position
+}
+
+impl Armatures {
+    pub(super) fn new(timings: Vec<Timing>) -> Self {
+        let held_for = vec![0; timings.len()];
+        Armatures { timings, held_for }
+    }
+
+    pub(super) fn timing(&self, switch: SwitchId) -> Timing {
+        self.timings[switch]
+    }
+
+    pub(super) fn held_for(&self, switch: SwitchId) -> u32 {
+        self.held_for[switch]
+    }
+
+    pub(super) fn set_held_for(&mut self, switch: SwitchId, ticks: u32) {
+        self.held_for[switch] = ticks;
+    }
+
+    /// Ticks each armature has been held against its position, which together with the switch
+    /// positions tells one relay state apart from another
+    pub(super) fn state(&self) -> &[u32] {
+        &self.held_for
+    }
+
+    /// Whether every armature agrees with its coil, i.e. no contact is about to move
+    pub(super) fn at_rest(&self) -> bool {
+        self.held_for.iter().all(|&held_for| held_for == 0)
+    }
+
+    /// Moves an armature one tick towards its coil state and returns the resulting position
+    pub(super) fn advance(&mut self, switch: SwitchId, energized: bool, position: bool) -> bool {
+        if energized == position {
+            self.held_for[switch] = 0;
+            return position;
+        }
+        self.held_for[switch] += 1;
+        let timing = self.timings[switch];
+        let delay = if energized { timing.pickup } else { timing.dropout };
+        if self.held_for[switch] >= delay {
+            self.held_for[switch] = 0;
+            energized
+        } else {
+            position
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::circuit::{CircuitBuilder, Engine, Handle, NodeId, Z3muError};
+
+    fn delayed_relay(timing: Timing, ticks_per_step: u32) -> (crate::circuit::Circuit, NodeId) {
+        let mut no = 0;
+        let c = CircuitBuilder::new()
+            .ticks_per_step(ticks_per_step)
+            .add_subcircuit(|mut scb| {
+                let g = scb.label("G");
+                scb.coil_with_timing(handle!("Ab", 0), g, timing);
+                (_, no, _) = scb.add_switch("ab_0", (g, None, None));
+                scb.trace(no);
+            })
+            .finalize();
+        (c, no)
+    }
+
+    #[test]
+    fn pickup_delay() {
+        for engine in [Engine::TwoPhase, Engine::EventDriven] {
+            let (mut c, no) = delayed_relay(Timing::new(3, 1), 1);
+            c.set_engine(engine);
+            for _ in 0..3 {
+                assert!(!c.is_picked_up(&handle!("ab", 0)));
+                c.step();
+            }
+            assert!(c.is_picked_up(&handle!("ab", 0)));
+            assert!(!c.traces[&no]);
+            c.step();
+            assert!(c.traces[&no]);
+        }
+    }
+
+    #[test]
+    fn oscillation_period_follows_delays() {
+        for engine in [Engine::TwoPhase, Engine::EventDriven] {
+            let mut coil_node = 0;
+            let mut c = CircuitBuilder::new().add_subcircuit(|mut scb| {
+                let g = scb.label("G");
+                (_, _, coil_node) = scb.add_switch("xy_-10", (g, None, None));
+                scb.coil_with_timing("Xy_-10", Some(coil_node), Timing::new(2, 3));
+            }).finalize();
+            c.set_engine(engine);
+            let mut positions = Vec::new();
+            for _ in 0..12 {
+                c.tick();
+                positions.push(c.is_picked_up(&handle!("xy", -10)));
+            }
+            let expected = [false, true, true, true, false, false, true, true, true, false, false, true];
+            assert_eq!(positions, expected);
+        }
+    }
+
+    #[test]
+    fn ticks_per_step() {
+        let (mut c, no) = delayed_relay(Timing::default(), 4);
+        c.step();
+        assert!(c.is_picked_up(&handle!("ab", 0)));
+        assert!(c.traces[&no]);
+
+        let (mut c, no) = delayed_relay(Timing::new(6, 1), 4);
+        c.step();
+        assert!(!c.is_picked_up(&handle!("ab", 0)));
+        c.step();
+        assert!(c.is_picked_up(&handle!("ab", 0)));
+        assert!(c.traces[&no]);
+    }
+
+    #[test]
+    fn sources_last_the_whole_step() {
+        let mut c = CircuitBuilder::new()
+            .ticks_per_step(3)
+            .add_subcircuit(|mut scb| {
+                let (g, input) = (scb.label("G"), scb.label("In"));
+                scb.coil_with_timing(handle!("Ab", 0), input, Timing::new(3, 1));
+                let (_, no, _) = scb.add_switch("ab_0", (g, None, None));
+                scb.trace(no);
+            })
+            .finalize();
+        c.set(&handle!("In"));
+        c.step();
+        assert!(c.is_picked_up(&handle!("ab", 0)));
+        c.step();
+        assert!(!c.is_picked_up(&handle!("ab", 0)));
+    }
+
+    #[test]
+    fn coils_of_one_relay_must_agree_on_timing() {
+        let result = CircuitBuilder::new()
+            .add_named_subcircuit("ab", |mut scb| {
+                let g = scb.label("G");
+                scb.coil_with_timing(handle!("Ab", 0), None, Timing::new(2, 1));
+                scb.coil_with_timing(handle!("Ab", 0, 1), None, Timing::new(3, 1));
+                scb.add_switch(handle!("ab", 0), (g, None, None));
+            })
+            .try_finalize();
+        assert_eq!(result.err(), Some(Z3muError::ConflictingTiming {
+            coil: handle!("Ab", 0, 1),
+            timing: Timing::new(3, 1),
+            existing: Timing::new(2, 1),
+            subcircuit: "ab".to_string(),
+        }));
+    }
+}
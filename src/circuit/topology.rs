@@ -0,0 +1,234 @@
+use std::ops::Index;
+
+use super::{NodeId, SwitchId};
+
+/// Compressed sparse rows: a flat `Vec<Vec<T>>` that never reallocates once built
+#[derive(Clone, Debug)]
+pub struct Csr<T> {
+    offsets: Vec<usize>, // row -> start of the row in items, plus a final end marker
+    items: Vec<T>,
+}
+
+impl<T> Csr<T> {
+    /// Builds a table with `rows` rows from unordered (row, item) pairs
+    pub fn from_pairs(rows: usize, pairs: Vec<(usize, T)>) -> Self {
+        let mut offsets = vec![0; rows + 1];
+        for (row, _) in &pairs {
+            offsets[row + 1] += 1;
+        }
+        for row in 0..rows {
+            offsets[row + 1] += offsets[row];
+        }
+        let mut slots: Vec<Option<T>> = std::iter::repeat_with(|| None).take(pairs.len()).collect();
+        let mut next = offsets.clone();
+        for (row, item) in pairs {
+            slots[next[row]] = Some(item);
+            next[row] += 1;
+        }
+        Csr { offsets, items: slots.into_iter().map(Option::unwrap).collect() }
+    }
+
+    pub fn rows(&self) -> usize {
+        self.offsets.len() - 1
+    }
+
+    pub fn row(&self, row: usize) -> &[T] {
+        &self.items[self.offsets[row]..self.offsets[row + 1]]
+    }
+}
+
+/// One side of a contact, as seen from a node touching it
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Contact {
+    pub switch: SwitchId,
+    pub other: NodeId,
+    pub closed_when: bool, // switch position in which the contact conducts (true for NO)
+}
+
+/// Which side of a changeover switch a contact is on
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ContactKind {
+    No, // normally open, closed while the relay is picked up
+    Nc, // normally closed, closed while the relay is released
+}
+
+impl ContactKind {
+    pub fn from_closed_when(closed_when: bool) -> Self {
+        if closed_when { ContactKind::No } else { ContactKind::Nc }
+    }
+}
+
+impl std::fmt::Display for ContactKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", match self {
+            ContactKind::No => "NO",
+            ContactKind::Nc => "NC",
+        })
+    }
+}
+
+/// Fixed-size set of bits, used for per-step switch state
+#[derive(Clone, Default, PartialEq, Eq)]
+pub struct BitSet {
+    words: Vec<u64>,
+    len: usize,
+}
+
+impl BitSet {
+    pub fn new(len: usize) -> Self {
+        BitSet { words: vec![0; len.div_ceil(64)], len }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn get(&self, i: usize) -> bool {
+        assert!(i < self.len);
+        self.words[i / 64] >> (i % 64) & 1 != 0
+    }
+
+    pub fn set(&mut self, i: usize, value: bool) {
+        assert!(i < self.len);
+        if value {
+            self.words[i / 64] |= 1 << (i % 64);
+        } else {
+            self.words[i / 64] &= !(1 << (i % 64));
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.words.fill(0);
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = bool> + '_ {
+        (0..self.len).map(|i| self.get(i))
+    }
+
+    /// Indices at which two sets of the same length differ
+    pub fn differences<'a>(&'a self, other: &'a BitSet) -> impl Iterator<Item = usize> + 'a {
+        assert_eq!(self.len, other.len);
+        self.words.iter().zip(&other.words).enumerate().flat_map(|(w, (a, b))| {
+            let mut diff = a ^ b;
+            std::iter::from_fn(move || {
+                if diff == 0 {
+                    return None;
+                }
+                let bit = diff.trailing_zeros() as usize;
+                diff &= diff - 1;
+                Some(w * 64 + bit)
+            })
+        })
+    }
+}
+
+impl std::hash::Hash for BitSet {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.words.hash(state);
+    }
+}
+
+impl Index<usize> for BitSet {
+    type Output = bool;
+
+    fn index(&self, i: usize) -> &bool {
+        if self.get(i) { &true } else { &false }
+    }
+}
+
+impl std::fmt::Debug for BitSet {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.iter().map(|b| if b { '1' } else { '0' }).collect::<String>())
+    }
+}
+
+/// Disjoint-set forest over nodes with path halving and union by size
+#[derive(Default)]
+pub struct UnionFind {
+    parent: Vec<NodeId>,
+    size: Vec<u32>,
+}
+
+impl UnionFind {
+    pub fn new(len: usize) -> Self {
+        let mut ret = UnionFind { parent: vec![0; len], size: vec![0; len] };
+        ret.reset();
+        ret
+    }
+
+    /// Splits every node back into its own set without reallocating
+    pub fn reset(&mut self) {
+        for (i, parent) in self.parent.iter_mut().enumerate() {
+            *parent = i;
+        }
+        self.size.fill(1);
+    }
+
+    pub fn find(&mut self, mut node: NodeId) -> NodeId {
+        while self.parent[node] != node {
+            self.parent[node] = self.parent[self.parent[node]];
+            node = self.parent[node];
+        }
+        node
+    }
+
+    pub fn union(&mut self, a: NodeId, b: NodeId) {
+        let (mut a, mut b) = (self.find(a), self.find(b));
+        if a == b {
+            return;
+        }
+        if self.size[a] < self.size[b] {
+            std::mem::swap(&mut a, &mut b);
+        }
+        self.parent[b] = a;
+        self.size[a] += self.size[b];
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn csr_rows() {
+        let csr = Csr::from_pairs(4, vec![(2, 'a'), (0, 'b'), (2, 'c'), (3, 'd')]);
+        assert_eq!(csr.rows(), 4);
+        assert_eq!(csr.row(0), &['b']);
+        assert_eq!(csr.row(1), &[] as &[char]);
+        assert_eq!(csr.row(2), &['a', 'c']);
+        assert_eq!(csr.row(3), &['d']);
+    }
+
+    #[test]
+    fn bitset() {
+        let mut bits = BitSet::new(130);
+        bits.set(0, true);
+        bits.set(64, true);
+        bits.set(129, true);
+        bits.set(64, false);
+        assert!(bits[0] && !bits[64] && bits[129]);
+        assert_eq!(bits.iter().filter(|b| *b).count(), 2);
+        let mut other = BitSet::new(130);
+        other.set(64, true);
+        other.set(129, true);
+        assert_eq!(bits.differences(&other).collect::<Vec<_>>(), vec![0, 64]);
+        bits.clear();
+        assert_eq!(bits, BitSet::new(130));
+    }
+
+    #[test]
+    fn union_find() {
+        let mut uf = UnionFind::new(6);
+        uf.union(0, 1);
+        uf.union(2, 3);
+        uf.union(1, 3);
+        assert_eq!(uf.find(0), uf.find(2));
+        assert_ne!(uf.find(0), uf.find(4));
+        uf.reset();
+        assert_ne!(uf.find(0), uf.find(1));
+    }
+}
@@ -0,0 +1,161 @@
+use super::{bus_members, unknown_label, Bus, Circuit, Handle, NodeId, SessionEntry, Z3muError};
+
+/// Whether `text` matches a glob `pattern` in which `*` stands for any run of characters and
+/// `?` for any single one
+pub(super) fn glob_match(pattern: &[u8], text: &[u8]) -> bool {
+    match (pattern.first(), text.first()) {
+        (None, None) => true,
+        (Some(b'*'), _) => glob_match(&pattern[1..], text) || (!text.is_empty() && glob_match(pattern, &text[1..])),
+        (Some(b'?'), Some(_)) => glob_match(&pattern[1..], &text[1..]),
+        (Some(p), Some(t)) if p == t => glob_match(&pattern[1..], &text[1..]),
+        _ => false,
+    }
+}
+
+impl Circuit {
+
+    /// Starts recording a label into the traces reported by `traced`, snapshots and observers
+    pub fn trace(&mut self, handle: &Handle) {
+        self.try_trace(handle).unwrap_or_else(|err| panic!("{}", err));
+    }
+
+    /// Like `trace`, but reports a missing label instead of panicking
+    pub fn try_trace(&mut self, handle: &Handle) -> Result<(), Z3muError> {
+        let node_id = self.node_of(handle).ok_or_else(|| unknown_label(handle))?;
+        self.trace_nodes([node_id]);
+        self.record_entry(|_| SessionEntry::Trace(handle.clone()));
+        Ok(())
+    }
+
+    pub fn untrace(&mut self, handle: &Handle) {
+        self.try_untrace(handle).unwrap_or_else(|err| panic!("{}", err));
+    }
+
+    /// Like `untrace`, but reports a missing label instead of panicking
+    pub fn try_untrace(&mut self, handle: &Handle) -> Result<(), Z3muError> {
+        let node_id = self.node_of(handle).ok_or_else(|| unknown_label(handle))?;
+        self.untrace_nodes([node_id]);
+        self.record_entry(|_| SessionEntry::Untrace(handle.clone()));
+        Ok(())
+    }
+
+    pub fn trace_bus(&mut self, bus: &Bus) {
+        let members: Vec<NodeId> = bus_members(&self.labels, bus).into_iter().map(|(_, node_id)| node_id).collect();
+        self.trace_nodes(members);
+        self.record_entry(|_| SessionEntry::TraceBus(bus.clone()));
+    }
+
+    pub fn untrace_bus(&mut self, bus: &Bus) {
+        let members: Vec<NodeId> = bus_members(&self.labels, bus).into_iter().map(|(_, node_id)| node_id).collect();
+        self.untrace_nodes(members);
+        self.record_entry(|_| SessionEntry::UntraceBus(bus.clone()));
+    }
+
+    /// Traces every label whose text form matches a glob such as `Ba_*` or `S_?`, returning how
+    /// many labels matched
+    pub fn trace_matching(&mut self, pattern: &str) -> usize {
+        let matching = self.labels_matching(pattern);
+        let count = matching.len();
+        self.trace_nodes(matching);
+        self.record_entry(|_| SessionEntry::TraceMatching(pattern.to_string()));
+        count
+    }
+
+    pub fn untrace_matching(&mut self, pattern: &str) -> usize {
+        let matching = self.labels_matching(pattern);
+        let count = matching.len();
+        self.untrace_nodes(matching);
+        self.record_entry(|_| SessionEntry::UntraceMatching(pattern.to_string()));
+        count
+    }
+
+    /// Traces every node, including those without a label
+    pub fn trace_everything(&mut self) {
+        self.trace_nodes(0..self.num_nodes);
+        self.record_entry(|_| SessionEntry::TraceEverything);
+    }
+
+    pub fn untrace_everything(&mut self) {
+        self.untrace_nodes(0..self.num_nodes);
+        self.record_entry(|_| SessionEntry::UntraceEverything);
+    }
+
+    fn labels_matching(&self, pattern: &str) -> Vec<NodeId> {
+        self.labels
+            .iter()
+            .filter(|(handle, _)| glob_match(pattern.as_bytes(), handle.to_string().as_bytes()))
+            .map(|(_, &node_id)| node_id)
+            .collect()
+    }
+
+    fn trace_nodes(&mut self, nodes: impl IntoIterator<Item = NodeId>) {
+        let mut changed = false;
+        for node_id in nodes {
+            changed |= self.traces.insert(node_id, self.powered[node_id]).is_none();
+        }
+        if changed {
+            self.notify_traces_changed();
+        }
+    }
+
+    fn untrace_nodes(&mut self, nodes: impl IntoIterator<Item = NodeId>) {
+        let mut changed = false;
+        for node_id in nodes {
+            changed |= self.traces.remove(&node_id).is_some();
+        }
+        if changed {
+            self.notify_traces_changed();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::circuit::{CircuitBuilder, Engine};
+    use crate::common::gate;
+
+    #[test]
+    fn globs() {
+        assert!(glob_match(b"Ba_*", b"Ba_-16"));
+        assert!(glob_match(b"S_?", b"S_5"));
+        assert!(!glob_match(b"S_?", b"S_23"));
+        assert!(glob_match(b"*_1", b"Bb_1"));
+        assert!(!glob_match(b"Ba_*", b"Bb_1"));
+    }
+
+    #[test]
+    fn trace_after_finalize() {
+        for engine in [Engine::TwoPhase, Engine::EventDriven] {
+            let mut c = CircuitBuilder::new()
+                .add_subcircuit(gate(bus!("Ab"), handle!("Ga"), bus!("Aa"), 0..=3))
+                .add_subcircuit(|mut scb| {
+                    for i in 0..=3 {
+                        scb.coil(handle!("Ab", i), None);
+                    }
+                })
+                .finalize();
+            c.set_engine(engine);
+            assert!(c.traced().is_empty());
+            c.trace_bus(&bus!("Aa"));
+            assert_eq!(c.trace_matching("G?"), 1);
+            assert_eq!(c.traced().len(), 5);
+
+            c.set_bus(&bus!("Ab"), 6);
+            c.set(&handle!("Ga"));
+            c.step();
+            c.set(&handle!("S", 5));
+            c.step();
+            assert_eq!(c.inspect_bus(&bus!("Aa")), 6);
+            assert!(!c.inspect(&handle!("Ab", 1)), "untraced labels can be inspected too");
+            let traced: Vec<String> = c.traced().into_iter().filter(|(_, powered)| *powered).map(|(name, _)| name).collect();
+            assert_eq!(traced, vec!["Aa_1", "Aa_2"]);
+
+            assert_eq!(c.untrace_matching("Aa_*"), 4);
+            c.untrace(&handle!("Ga"));
+            assert!(c.traced().is_empty());
+            c.trace_everything();
+            assert_eq!(c.traced().len(), c.num_nodes);
+        }
+    }
+}
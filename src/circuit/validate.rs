@@ -0,0 +1,228 @@
+use std::collections::{HashMap, HashSet};
+
+use super::{CircuitBuilder, Handle, NodeId};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Severity {
+    Info,
+    Warning,
+    Error,
+}
+
+impl std::fmt::Display for Severity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", match self {
+            Severity::Info => "info",
+            Severity::Warning => "warning",
+            Severity::Error => "error",
+        })
+    }
+}
+
+/// Something suspicious about a circuit under construction
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Issue {
+    CoillessRelay { relay: Handle }, // switches named after a relay that has no coil
+    UnusedCoil { coil: Handle }, // coil whose relay has no switches
+    OrphanContact { switch: Handle, index: usize }, // nth switch of the relay can switch nothing
+    FloatingNode { node: NodeId, label: Handle }, // labelled node nothing else touches
+    SingleUseLabel { label: Handle }, // label mentioned only once that is not G, a coil or on a switch, probably a typo
+}
+
+impl Issue {
+    pub fn severity(&self) -> Severity {
+        match self {
+            Issue::CoillessRelay { .. } => Severity::Error,
+            Issue::UnusedCoil { .. } | Issue::OrphanContact { .. } | Issue::FloatingNode { .. } => Severity::Warning,
+            Issue::SingleUseLabel { .. } => Severity::Info,
+        }
+    }
+}
+
+impl std::fmt::Display for Issue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Issue::CoillessRelay { relay } => write!(f, "relay {} has switches but no coil", relay),
+            Issue::UnusedCoil { coil } => write!(f, "coil {} is not connected to any switches", coil),
+            Issue::OrphanContact { switch, index } => write!(f, "switch {} of relay {} connects to nothing", index, switch),
+            Issue::FloatingNode { node, label } => write!(f, "node {} ({}) touches no switch or coil", label, node),
+            Issue::SingleUseLabel { label } => write!(f, "label {} is only used once", label),
+        }
+    }
+}
+
+/// Issues found by `CircuitBuilder::validate`, most severe first
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ValidationReport {
+    pub issues: Vec<Issue>,
+}
+
+impl ValidationReport {
+    pub fn has_errors(&self) -> bool {
+        self.issues.iter().any(|issue| issue.severity() == Severity::Error)
+    }
+
+    pub fn at_least(&self, severity: Severity) -> impl Iterator<Item = &Issue> {
+        self.issues.iter().filter(move |issue| issue.severity() >= severity)
+    }
+}
+
+impl std::fmt::Display for ValidationReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for issue in &self.issues {
+            writeln!(f, "{}: {}", issue.severity(), issue)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for ValidationReport {}
+
+impl CircuitBuilder {
+
+    /// Makes `finalize` panic, and `try_finalize` fail, with the validation report if it has any errors
+    pub fn strict(mut self) -> Self {
+        self.strict = true;
+        self
+    }
+
+    /// Checks the netlist built so far
+    ///
+    /// A switch is an orphan when its pole, or both of its throws, touch nothing else: no other
+    /// switch terminal, coil, rail or label. Throws left open on their own are normal, since every
+    /// switch is a changeover.
+    pub fn validate(&self) -> ValidationReport {
+        let mut attachments = vec![0u32; self.num_nodes];
+        let mut on_switch = vec![false; self.num_nodes];
+        for switch in &self.switches {
+            for node in [switch.pole, switch.no, switch.nc] {
+                attachments[node] += 1;
+                on_switch[node] = true;
+            }
+        }
+        for &node in self.coils.values() {
+            attachments[node] += 1;
+        }
+        for (_, pos, neg) in &self.loads {
+            attachments[*pos] += 1;
+            attachments[*neg] += 1;
+        }
+        for (node, _) in &self.ties {
+            attachments[*node] += 1;
+        }
+        let labelled: HashSet<NodeId> = self.labels.values().copied().collect();
+        let connected = |node: NodeId| attachments[node] > 1 || labelled.contains(&node);
+
+        let mut issues = Vec::new();
+        let coil_relays: HashSet<Handle> = self.coils
+            .keys()
+            .chain(self.loads.iter().map(|(handle, _, _)| handle))
+            .map(CircuitBuilder::coil_to_switch_name)
+            .collect();
+        let mut relays: Vec<&Handle> = Vec::new();
+        let mut switch_counts: HashMap<&Handle, usize> = HashMap::new();
+        for switch in &self.switches {
+            let index = switch_counts.entry(&switch.name).or_insert_with(|| {
+                relays.push(&switch.name);
+                0
+            });
+            if !connected(switch.pole) || !(connected(switch.no) || connected(switch.nc)) {
+                issues.push(Issue::OrphanContact { switch: switch.name.clone(), index: *index });
+            }
+            *index += 1;
+        }
+        relays.sort_by_key(|relay| relay.to_string());
+        issues.extend(relays
+            .into_iter()
+            .filter(|relay| !coil_relays.contains(relay))
+            .map(|relay| Issue::CoillessRelay { relay: relay.clone() }));
+
+        let mut coils: Vec<&Handle> = self.coils.keys().chain(self.loads.iter().map(|(handle, _, _)| handle)).collect();
+        coils.sort_by_key(|coil| coil.to_string());
+        let coil_handles: HashSet<&Handle> = coils.iter().copied().collect();
+        issues.extend(coils
+            .iter()
+            .filter(|coil| !switch_counts.contains_key(&CircuitBuilder::coil_to_switch_name(coil)))
+            .map(|coil| Issue::UnusedCoil { coil: (*coil).clone() }));
+
+        let mut labels: Vec<(&Handle, NodeId)> = self.labels.iter().map(|(handle, &node)| (handle, node)).collect();
+        labels.sort_by_key(|(handle, _)| handle.to_string());
+        let mut floating = HashSet::new();
+        for &(label, node) in &labels {
+            if node != 0 && attachments[node] == 0 && floating.insert(node) {
+                issues.push(Issue::FloatingNode { node, label: label.clone() });
+            }
+        }
+        issues.extend(labels
+            .iter()
+            .filter(|(label, node)| *node != 0 && !on_switch[*node] && self.label_uses.get(*label) == Some(&1) && !coil_handles.contains(label))
+            .map(|(label, _)| Issue::SingleUseLabel { label: (*label).clone() }));
+
+        issues.sort_by_key(|issue| std::cmp::Reverse(issue.severity()));
+        ValidationReport { issues }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::circuit::Z3muError;
+
+    fn build() -> CircuitBuilder {
+        CircuitBuilder::new().add_subcircuit(|mut scb| {
+            let (g, input) = (scb.label("G"), scb.label("In"));
+            let coil = scb.coil(handle!("Ab", 0), input);
+            scb.add_switch(handle!("ab", 0), (g, None, coil));
+            scb.add_switch(handle!("ab", 0), (None, None, None));
+            scb.add_switch(handle!("xy", 1), (g, input, None));
+            scb.coil(handle!("Cd", 2), None);
+            scb.label("Spare");
+            scb.label("In");
+        })
+    }
+
+    #[test]
+    fn reports_netlist_problems() {
+        let report = build().validate();
+        assert!(report.has_errors());
+        assert_eq!(report.to_string(), [
+            "error: relay xy_1 has switches but no coil",
+            "warning: switch 1 of relay ab_0 connects to nothing",
+            "warning: coil Cd_2 is not connected to any switches",
+            "warning: node Spare (8) touches no switch or coil",
+            "info: label Spare is only used once",
+            "",
+        ].join("\n"));
+        assert_eq!(report.at_least(Severity::Warning).count(), 4);
+
+        // not strict, so errors are only logged
+        build().finalize();
+    }
+
+    #[test]
+    #[should_panic(expected = "relay xy_1 has switches but no coil")]
+    fn strict_finalize_fails_on_errors() {
+        build().strict().finalize();
+    }
+
+    #[test]
+    fn strict_try_finalize_returns_report() {
+        let err = build().strict().try_finalize().err().unwrap();
+        assert_eq!(err, Z3muError::Validation(build().validate()));
+        assert!(build().try_finalize().is_ok());
+    }
+
+    #[test]
+    fn labels_on_switches_are_not_typos() {
+        let report = CircuitBuilder::new()
+            .add_subcircuit(|mut scb| {
+                let (ei, out) = (scb.label("Ei"), scb.label("Out"));
+                scb.coil(handle!("Ab", 0), out);
+                scb.add_switch(handle!("ab", 0), (ei, out, None));
+                scb.label("Typo");
+            })
+            .validate();
+        let single_use: Vec<&Issue> = report.issues.iter().filter(|issue| matches!(issue, Issue::SingleUseLabel { .. })).collect();
+        assert_eq!(single_use, vec![&Issue::SingleUseLabel { label: handle!("Typo") }]);
+    }
+}
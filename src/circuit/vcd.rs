@@ -0,0 +1,258 @@
+use std::collections::{BTreeMap, HashMap};
+use std::io::{self, Write};
+
+use super::{Bus, Circuit, Handle, SimObserver};
+
+/// Writes the traced nodes of a `Circuit` to a Value Change Dump as the simulation runs
+///
+/// Traced labels that share a bus name become one vector signal spanning their indices, so
+/// `Ba_1` to `Ba_-16` appear as `Ba [1:-16]`; other traced nodes are single wires. One tick of
+/// the relay clock is dumped as one millisecond. Add the writer to the circuit as a
+/// `SimObserver`, usually behind an `Rc<RefCell<_>>` so that `finish` can be called afterwards.
+///
+/// The signals are declared once, in the header, from the nodes traced when the writer is made.
+/// A VCD cannot declare signals after its header, so tracing or untracing nodes afterwards is an
+/// error that `finish` reports.
+pub struct VcdWriter<W: Write> {
+    out: W,
+    signals: Vec<Signal>,
+    bits: HashMap<String, (usize, usize)>, // trace name -> (signal, bit from the least significant)
+    dirty: Vec<usize>, // signals changed in the pending tick
+    pending_tick: u64,
+    error: Option<io::Error>, // first write error, reported by `finish`
+}
+
+struct Signal {
+    id: String,
+    values: Vec<Option<bool>>, // least significant bit first; None for indices nobody traces
+    dirty: bool,
+}
+
+impl<W: Write> VcdWriter<W> {
+
+    /// Writes the header and the current values of the traced nodes
+    pub fn new(mut out: W, circuit: &Circuit) -> io::Result<Self> {
+        let traced = circuit.traced();
+        let mut buses: BTreeMap<String, BTreeMap<i8, (String, bool)>> = BTreeMap::new();
+        let mut wires: Vec<(String, bool)> = Vec::new();
+        for (name, powered) in traced {
+            match name.parse() {
+                Ok(Handle { name: bus, index: Some(index), sup }) => {
+                    buses.entry(Bus::new(bus, sup).to_string()).or_default().insert(index, (name, powered));
+                }
+                _ => wires.push((name, powered)),
+            }
+        }
+
+        writeln!(out, "$version z3mu $end")?;
+        writeln!(out, "$timescale 1ms $end")?;
+        writeln!(out, "$scope module z3mu $end")?;
+        let mut signals = Vec::new();
+        let mut bits = HashMap::new();
+        for (bus, members) in buses {
+            let (&lsb, _) = members.first_key_value().unwrap();
+            let (&msb, _) = members.last_key_value().unwrap();
+            let id = VcdWriter::<W>::id(signals.len());
+            if members.len() == 1 {
+                let (name, _) = &members[&lsb];
+                writeln!(out, "$var wire 1 {} {} $end", id, name)?;
+            } else {
+                writeln!(out, "$var wire {} {} {} [{}:{}] $end", msb as i32 - lsb as i32 + 1, id, bus, msb, lsb)?;
+            }
+            let mut values = vec![None; (msb as i32 - lsb as i32 + 1) as usize];
+            for (index, (name, powered)) in members {
+                let bit = (index as i32 - lsb as i32) as usize;
+                values[bit] = Some(powered);
+                bits.insert(name, (signals.len(), bit));
+            }
+            signals.push(Signal { id, values, dirty: false });
+        }
+        for (name, powered) in wires {
+            let id = VcdWriter::<W>::id(signals.len());
+            writeln!(out, "$var wire 1 {} {} $end", id, name)?;
+            bits.insert(name, (signals.len(), 0));
+            signals.push(Signal { id, values: vec![Some(powered)], dirty: false });
+        }
+        writeln!(out, "$upscope $end")?;
+        writeln!(out, "$enddefinitions $end")?;
+        writeln!(out, "#{}", circuit.ticks())?;
+        writeln!(out, "$dumpvars")?;
+        for signal in &signals {
+            VcdWriter::write_value(&mut out, signal)?;
+        }
+        writeln!(out, "$end")?;
+
+        Ok(VcdWriter { out, signals, bits, dirty: Vec::new(), pending_tick: circuit.ticks(), error: None })
+    }
+
+    /// Identifier of the nth signal, in base 94 over the printable ASCII characters
+    fn id(mut n: usize) -> String {
+        let mut id = String::new();
+        loop {
+            id.push((b'!' + (n % 94) as u8) as char);
+            n /= 94;
+            if n == 0 {
+                return id;
+            }
+        }
+    }
+
+    fn write_value(out: &mut W, signal: &Signal) -> io::Result<()> {
+        let bit = |value: &Option<bool>| match value {
+            Some(true) => '1',
+            Some(false) => '0',
+            None => 'x',
+        };
+        if signal.values.len() == 1 {
+            writeln!(out, "{}{}", bit(&signal.values[0]), signal.id)
+        } else {
+            writeln!(out, "b{} {}", signal.values.iter().rev().map(bit).collect::<String>(), signal.id)
+        }
+    }
+
+    /// Writes the changes buffered for the pending tick
+    fn flush_tick(&mut self) -> io::Result<()> {
+        if self.dirty.is_empty() {
+            return Ok(());
+        }
+        writeln!(self.out, "#{}", self.pending_tick)?;
+        for signal in self.dirty.drain(..) {
+            let signal = &mut self.signals[signal];
+            signal.dirty = false;
+            VcdWriter::write_value(&mut self.out, signal)?;
+        }
+        Ok(())
+    }
+
+    fn record(&mut self, result: io::Result<()>) {
+        if let Err(err) = result {
+            self.error.get_or_insert(err);
+        }
+    }
+
+    /// Writes any buffered changes and flushes the output, returning the first error met so far
+    pub fn finish(&mut self) -> io::Result<()> {
+        let result = self.flush_tick();
+        self.record(result);
+        let result = self.out.flush();
+        self.record(result);
+        self.error.take().map_or(Ok(()), Err)
+    }
+
+    pub fn get_ref(&self) -> &W {
+        &self.out
+    }
+}
+
+impl<W: Write> SimObserver for VcdWriter<W> {
+    fn step_ended(&mut self, _step: u64) {
+        let result = self.flush_tick();
+        self.record(result);
+    }
+
+    fn traces_changed(&mut self, traced: &[(String, bool)]) {
+        let declared = traced.len() == self.bits.len() && traced.iter().all(|(name, _)| self.bits.contains_key(name));
+        if !declared {
+            let names: Vec<&str> = traced.iter().map(|(name, _)| name.as_str()).collect();
+            let message = format!("trace set changed after the VCD header was written: now {}", names.join(", "));
+            self.record(Err(io::Error::new(io::ErrorKind::InvalidInput, message)));
+        }
+    }
+
+    fn trace_changed(&mut self, tick: u64, node: &str, powered: bool) {
+        if tick != self.pending_tick {
+            let result = self.flush_tick();
+            self.record(result);
+            self.pending_tick = tick;
+        }
+        let Some(&(signal, bit)) = self.bits.get(node) else {
+            return;
+        };
+        let signal_ref = &mut self.signals[signal];
+        signal_ref.values[bit] = Some(powered);
+        if !signal_ref.dirty {
+            signal_ref.dirty = true;
+            self.dirty.push(signal);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    use super::*;
+    use crate::circuit::CircuitBuilder;
+    use crate::common::gate;
+
+    #[test]
+    fn dumps_buses_as_vectors() {
+        let mut c = CircuitBuilder::new()
+            .add_subcircuit(gate(bus!("Ab"), handle!("Ga"), bus!("Aa"), -2..=1))
+            .add_subcircuit(|mut scb| {
+                for i in -2..=1 {
+                    scb.coil(handle!("Ab", i), None);
+                    let aa = scb.label(handle!("Aa", i));
+                    scb.trace(aa);
+                }
+                let ga = scb.label("Ga");
+                scb.trace(ga);
+            })
+            .finalize();
+        let vcd = Rc::new(RefCell::new(VcdWriter::new(Vec::new(), &c).unwrap()));
+        c.add_observer(vcd.clone());
+        c.set(&handle!("Ab", 1));
+        c.set(&handle!("Ab", -2));
+        c.set(&handle!("Ga"));
+        c.step();
+        c.set(&handle!("S", 5));
+        c.step();
+        c.step();
+        vcd.borrow_mut().finish().unwrap();
+
+        let text = String::from_utf8(vcd.borrow().get_ref().clone()).unwrap();
+        let body: Vec<&str> = text.lines().skip_while(|line| *line != "$scope module z3mu $end").collect();
+        assert_eq!(body, vec![
+            "$scope module z3mu $end",
+            "$var wire 4 ! Aa [1:-2] $end",
+            "$var wire 1 \" Ga $end",
+            "$upscope $end",
+            "$enddefinitions $end",
+            "#0",
+            "$dumpvars",
+            "b0000 !",
+            "0\"",
+            "$end",
+            "#1",
+            "1\"",
+            "#2",
+            "b1001 !",
+            "0\"",
+            "#3",
+            "b0000 !",
+        ]);
+    }
+
+    #[test]
+    fn tracing_after_the_header_is_an_error() {
+        let mut c = CircuitBuilder::new()
+            .add_subcircuit(|mut scb| {
+                let ga = scb.label("Ga");
+                scb.trace(ga);
+                scb.label("Gb");
+            })
+            .finalize();
+        let vcd = Rc::new(RefCell::new(VcdWriter::new(Vec::new(), &c).unwrap()));
+        c.add_observer(vcd.clone());
+        c.trace(&handle!("Ga"));
+        c.step();
+        vcd.borrow_mut().finish().unwrap();
+
+        c.trace(&handle!("Gb"));
+        c.step();
+        let err = vcd.borrow_mut().finish().unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        assert_eq!(err.to_string(), "trace set changed after the VCD header was written: now Ga, Gb");
+    }
+}
@@ -1,4 +1,6 @@
-use super::circuit::{Handle, Bus, SubcircuitBuilder, CircuitBuilder};
+use std::ops::RangeInclusive;
+
+use super::circuit::{Handle, Bus, SubcircuitBuilder, CircuitBuilder, Port, Subcircuit};
 
 pub fn gate<'a, I: Iterator<Item = i8> + 'a>(from: Bus, gate: Handle, to: Bus, indices: I) -> impl FnOnce(SubcircuitBuilder) {
     move |mut builder: SubcircuitBuilder| {
@@ -26,6 +28,63 @@ pub fn gate_const<'a, I: Iterator<Item = i8> + 'a>(k: i8, gate: Handle, to: Bus,
     }
 }
 
+/// `gate` with ports `From`, `Gate` and `To`, for instantiating once per gated transfer
+pub struct Gate {
+    indices: RangeInclusive<i8>,
+}
+
+impl Gate {
+    pub fn new(indices: RangeInclusive<i8>) -> Self {
+        Gate { indices }
+    }
+}
+
+impl Subcircuit for Gate {
+    fn name(&self) -> String {
+        "gate".into()
+    }
+
+    fn ports(&self) -> Vec<Port> {
+        vec![
+            Port::input_bus("From", self.indices.clone()),
+            Port::input("Gate"),
+            Port::output_bus("To", self.indices.clone()),
+        ]
+    }
+
+    fn build(&self, mut scb: SubcircuitBuilder) {
+        scb.global("S_*");
+        gate(bus!("From"), handle!("Gate"), bus!("To"), self.indices.clone())(scb)
+    }
+}
+
+/// `gate_const` with ports `Gate` and `To`
+pub struct ConstGate {
+    k: i8,
+    indices: RangeInclusive<i8>,
+}
+
+impl ConstGate {
+    pub fn new(k: i8, indices: RangeInclusive<i8>) -> Self {
+        ConstGate { k, indices }
+    }
+}
+
+impl Subcircuit for ConstGate {
+    fn name(&self) -> String {
+        "constant gate".into()
+    }
+
+    fn ports(&self) -> Vec<Port> {
+        vec![Port::input("Gate"), Port::output_bus("To", self.indices.clone())]
+    }
+
+    fn build(&self, mut scb: SubcircuitBuilder) {
+        scb.global("S_*");
+        gate_const(self.k, handle!("Gate"), bus!("To"), self.indices.clone())(scb)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -65,6 +124,43 @@ mod tests {
         assert_eq!(c.inspect_bus(&bus!("Aa")), -123);
     }
 
+    #[test]
+    fn gate_test_held_input() {
+        let mut c = CircuitBuilder::new()
+            .add_subcircuit(gate(bus!("Ab"), handle!("Ga"), bus!("Aa"), 0..=7))
+            .add_subcircuit(|mut scb| {
+                for i in 0..=7 {
+                    let ab = scb.coil(handle!("Ab", i), None);
+                    let aa = scb.label(handle!("Aa", i));
+                    scb.trace_all([ab, aa]);
+                }
+            })
+            .finalize();
+
+        c.hold_bus(&bus!("Ab"), -123);
+        assert_eq!(c.held().len(), 3);
+        for _ in 0..2 {
+            c.set(&handle!("Ga"));
+            c.step();
+            c.set(&handle!("S", 5));
+            c.step();
+            assert_eq!(c.inspect_bus(&bus!("Aa")), -123);
+
+            c.step();
+            c.set(&handle!("S", 5));
+            c.step();
+            assert_eq!(c.inspect_bus(&bus!("Aa")), 0);
+        }
+
+        c.release_bus(&bus!("Ab"));
+        assert!(c.held().is_empty());
+        c.set(&handle!("Ga"));
+        c.step();
+        c.set(&handle!("S", 5));
+        c.step();
+        assert_eq!(c.inspect_bus(&bus!("Aa")), 0);
+    }
+
     #[test]
     fn gate_const_test() {
         let mut c = CircuitBuilder::new()
@@ -1,8 +1,7 @@
-use crate::circuit::{SubcircuitBuilder, CircuitBuilder, Handle, Bus, NodeId};
-
-#[macro_use]
-pub mod circuit;
-pub mod common;
+use z3mu::{handle, bus};
+use z3mu::circuit::{SubcircuitBuilder, CircuitBuilder, Handle, Bus};
+use z3mu::circuit::clock::PHASES;
+use z3mu::common::{ConstGate, Gate};
 
 fn main() {
     env_logger::init();
@@ -61,26 +60,24 @@ fn main() {
     };
 
     // Figure 5
-    // Copies Af into Aa upon activating Ea
-    let figure5a = common::gate(bus!("Af"), handle!("Ea"), bus!("Aa"), 0..=6);
-    let figure5b = common::gate(bus!("Af"), handle!("Eb"), bus!("Ab"), 0..=6);
+    // Gates copying one register into another, e.g. Af into Aa upon activating Ea in 5a
+    let gate = |from: &str, gate: &str, to: &str| [("From", bus!(from).into()), ("Gate", handle!(gate).into()), ("To", bus!(to).into())];
+    let const_gate = |gate: &str, to: &str| [("Gate", handle!(gate).into()), ("To", bus!(to).into())];
+    let (gate6, gate7, gate16) = (Gate::new(0..=6), Gate::new(0..=7), Gate::new(-16..=0));
     // TODO: shifted gate Ee
-    let figure5d = common::gate(bus!("Ae"), handle!("Ec"), bus!("Aa"), 0..=7);
-    let figure5e = common::gate(bus!("Ae"), handle!("Ed"), bus!("Ab"), 0..=7);
     // TODO: wire Fa into Fpq shifter
     // TODO: wire Fb into Fhiklm shifter
     // TODO: wire Fc into Fpq shifter
     // TODO: wire Fd into Fhiklm shifter
-    let figure5j = common::gate(bus!("Be"), handle!("Ff"), bus!("Bf"), -16..=0);
     // TODO: shifted gate Be'_1
     // TODO: read input into Ba using Zabcd
-    let figure5m = common::gate_const(-4i8, handle!("Ei"), bus!("Ab"), 0..=7);
-    let figure5n = common::gate_const( 3i8, handle!("Eh"), bus!("Ab"), 0..=7);
-    let figure5o = common::gate_const(13i8, handle!("Eg"), bus!("Aa"), 0..=7);
 
     // Figure 6
     // Shifts input into Ba by -2Fp + Fq bits
     let figure6 = |mut scb: SubcircuitBuilder| {
+        for global in ["Ba_*", "Fp", "Fq"] {
+            scb.global(global);
+        }
         let (_, left2, prev_coil) = SubcircuitBuilder::chain((None, None, None), (-16..=1).rev(), |(left1, left2, prev_coil), i| {
             let input_name = if i == 0 { "0".into() } else { format!("{:+}", i) };
             let input = scb.label(input_name.as_str());
@@ -95,6 +92,9 @@ fn main() {
     // Figure 7
     // Shifts input into Bb by -16Fh + 8Fi + 4Fk + 2Fl + Fm bits
     let figure7 = |mut scb: SubcircuitBuilder| {
+        for global in ["Bb_*", "Fh", "Fi", "Fk", "Fl", "Fm"] {
+            scb.global(global);
+        }
         let fh_nodes: [_; 18] = (-16..=1).rev().map(|i| {
             let input_name = if i == 0 { "0".into() } else { format!("{:+}", i) };
             let input = scb.label(input_name.as_str());
@@ -135,27 +135,24 @@ fn main() {
     };
 
     let mut c = CircuitBuilder::new()
-        .add_subcircuit(figure4)
-        .add_subcircuit(figure5a)
-        .add_subcircuit(figure5b)
-        .add_subcircuit(figure5d)
-        .add_subcircuit(figure5e)
-        .add_subcircuit(figure5j)
-        .add_subcircuit(figure5m)
-        .add_subcircuit(figure5n)
-        .add_subcircuit(figure5o)
-        .add_subcircuit(figure6)
-        .add_subcircuit(figure7)
-        .add_subcircuit(|mut scb| {
-            let x: Vec<NodeId> = (0..=7).map(|i| scb.label(handle!("Ab", i))).collect();
-            scb.trace_all(x);
-        })
+        .with_phase_clock()
+        .add_named_subcircuit("figure4", figure4)
+        .instantiate("figure5a", &gate6, gate("Af", "Ea", "Aa"))
+        .instantiate("figure5b", &gate6, gate("Af", "Eb", "Ab"))
+        .instantiate("figure5d", &gate7, gate("Ae", "Ec", "Aa"))
+        .instantiate("figure5e", &gate7, gate("Ae", "Ed", "Ab"))
+        .instantiate("figure5j", &gate16, gate("Be", "Ff", "Bf"))
+        .instantiate("figure5m", &ConstGate::new(-4, 0..=7), const_gate("Ei", "Ab"))
+        .instantiate("figure5n", &ConstGate::new(3, 0..=7), const_gate("Eh", "Ab"))
+        .instantiate("figure5o", &ConstGate::new(13, 0..=7), const_gate("Eg", "Aa"))
+        .add_scoped_subcircuit("figure6", figure6)
+        .add_scoped_subcircuit("figure7", figure7)
         .finalize();
 
-    c.set(&handle!("Ei"));
-    c.step();
-    c.set(&handle!("S", 5));
-    c.step();
+    c.hold(&handle!("Ei"));
+    for _ in 0..PHASES {
+        c.step();
+    }
     c.inspect_bus(&bus!("Ab"));
 }
 
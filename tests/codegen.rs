@@ -0,0 +1,177 @@
+//! Runs the Rust generated by `Circuit::to_rust` against the interpreter
+//!
+//! The generated models live in `tests/codegen/` so that cargo compiles them along with this
+//! test. Each test first checks that its model is exactly what `to_rust` emits today; run with
+//! `Z3MU_BLESS=1` to rewrite the models after changing the code generator.
+
+use std::path::Path;
+
+use z3mu::{bus, handle};
+use z3mu::circuit::{Bus, Circuit, CircuitBuilder, Handle, SubcircuitBuilder, Timing};
+use z3mu::circuit::clock::PHASES;
+use z3mu::common::gate;
+
+/// What the tests need of a generated `State`, whose `Handle` type is the model's own
+trait Model: Default {
+    fn set(&mut self, handle: &Handle);
+    fn step(&mut self);
+    fn inspect(&self, handle: &Handle) -> bool;
+    fn positions(&self) -> Vec<bool>;
+}
+
+macro_rules! model {
+    ($name:ident) => {
+        #[allow(dead_code, clippy::all)]
+        mod $name {
+            include!(concat!("codegen/", stringify!($name), ".rs"));
+        }
+
+        impl Model for $name::State {
+            fn set(&mut self, handle: &Handle) {
+                $name::State::set(self, &$name::Handle::new(handle.name.clone(), handle.index, handle.sup))
+            }
+
+            fn step(&mut self) {
+                $name::State::step(self)
+            }
+
+            fn inspect(&self, handle: &Handle) -> bool {
+                $name::State::inspect(self, &$name::Handle::new(handle.name.clone(), handle.index, handle.sup))
+            }
+
+            fn positions(&self) -> Vec<bool> {
+                self.positions.to_vec()
+            }
+        }
+    };
+}
+
+model!(gate_model);
+model!(delays_model);
+model!(loops_model);
+model!(clock_model);
+
+/// Checks that `tests/codegen/<name>.rs` is the code generated for `c`, or rewrites it when
+/// blessing
+fn assert_generated(name: &str, c: &Circuit) {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/codegen").join(format!("{}.rs", name));
+    let source = c.to_rust();
+    if std::env::var_os("Z3MU_BLESS").is_some() {
+        std::fs::write(&path, source).unwrap();
+        return;
+    }
+    let checked_in = std::fs::read_to_string(&path).unwrap();
+    assert!(checked_in == source, "{} is out of date; rerun with Z3MU_BLESS=1", path.display());
+}
+
+/// Applies `schedule` one step at a time to both `c` and its compiled model, checking that they
+/// agree on the traced labels and the switch positions after every step
+fn assert_compiled_matches<M: Model>(name: &str, mut c: Circuit, traced: &[Handle], schedule: &[Vec<Handle>]) {
+    assert_generated(name, &c);
+    let mut model = M::default();
+    for (step, inputs) in schedule.iter().enumerate() {
+        for input in inputs {
+            c.set(input);
+            model.set(input);
+        }
+        c.step();
+        model.step();
+        for handle in traced {
+            assert_eq!(model.inspect(handle), c.inspect(handle), "{} diverged on {} at step {}", name, handle, step);
+        }
+        let positions: Vec<bool> = c.snapshot().switches.into_iter().map(|(_, position, _)| position).collect();
+        assert_eq!(model.positions(), positions, "{} switches diverged at step {}", name, step);
+    }
+}
+
+#[test]
+fn compiled_gate_matches_interpreter() {
+    let c = CircuitBuilder::new()
+        .add_subcircuit(gate(bus!("Ab"), handle!("Ga"), bus!("Aa"), 0..=7))
+        .add_subcircuit(|mut scb| {
+            for i in 0..=7 {
+                let ab = scb.coil(handle!("Ab", i), None);
+                let aa = scb.label(handle!("Aa", i));
+                scb.trace_all([ab, aa]);
+            }
+        })
+        .finalize();
+    let traced: Vec<Handle> = (0..=7).map(|i| handle!("Aa", i)).collect();
+    let mut load = vec![handle!("Ga")];
+    load.extend((0..=7).filter(|i| (-123i32 >> i) & 1 != 0).map(|i| handle!("Ab", i)));
+    let schedule = vec![load, vec![handle!("S", 5)], vec![], vec![handle!("S", 5)]];
+    assert_compiled_matches::<gate_model::State>("gate_model", c, &traced, &schedule);
+}
+
+#[test]
+fn compiled_delays_match_interpreter() {
+    let c = CircuitBuilder::new()
+        .ticks_per_step(2)
+        .add_subcircuit(|mut scb| {
+            let input = scb.label("In");
+            let g = scb.label("G");
+            let last = SubcircuitBuilder::chain(input, 0..4, |coil_pos, i| {
+                scb.coil_with_timing(handle!("Aa", i), coil_pos, Timing::new(1 + i as u32, 2));
+                let out = scb.label(handle!("out", i));
+                scb.trace(out);
+                scb.add_switch(handle!("aa", i), (g, out, None));
+                out
+            });
+            // self-interrupting buzzer at the end of the chain
+            let (_, _, buzz) = scb.add_switch("bz", (last, None, None));
+            scb.coil("Bz", buzz);
+            scb.trace(buzz);
+        })
+        .finalize();
+    let traced: Vec<Handle> = (0..4).map(|i| handle!("out", i)).collect();
+    let schedule: Vec<Vec<Handle>> = (0..12).map(|step| if step < 6 { vec![handle!("In")] } else { vec![] }).collect();
+    assert_compiled_matches::<delays_model::State>("delays_model", c, &traced, &schedule);
+}
+
+#[test]
+fn compiled_contact_loops_match_interpreter() {
+    let c = CircuitBuilder::new()
+        .add_subcircuit(|mut scb| {
+            let g = scb.label("G");
+            let (x, y) = (scb.label("X"), scb.label("Y"));
+            scb.trace_all([x, y]);
+            for (coil, input) in [("Aa", "Ia"), ("Ab", "Ib"), ("Ac", "Ic")] {
+                let input = scb.label(input);
+                scb.coil(coil, input);
+            }
+            // X and Y are each fed from G and bridged to one another
+            scb.add_switch("aa", (g, x, None));
+            scb.add_switch("ab", (g, y, None));
+            scb.add_switch("ac", (x, y, None));
+        })
+        .finalize();
+    let traced = vec![handle!("X"), handle!("Y")];
+    let schedule = vec![
+        vec![handle!("Ia")],
+        vec![handle!("Ia"), handle!("Ic")],
+        vec![handle!("Ic")],
+        vec![handle!("Ib"), handle!("Ic")],
+        vec![],
+    ];
+    assert_compiled_matches::<loops_model::State>("loops_model", c, &traced, &schedule);
+}
+
+#[test]
+fn compiled_clock_and_held_inputs_match_interpreter() {
+    let mut c = CircuitBuilder::new()
+        .with_phase_clock()
+        .add_subcircuit(|mut scb| {
+            let (g, input, s23) = (scb.label("G"), scb.label("In"), scb.label(handle!("S", 23)));
+            scb.coil(handle!("Aa", 0), input);
+            scb.coil(handle!("Ab", 0), s23);
+            let (_, mid, _) = scb.add_switch(handle!("aa", 0), (g, None, None));
+            let out = scb.label("out");
+            scb.trace(out);
+            scb.add_switch(handle!("ab", 0), (mid, out, None));
+        })
+        .finalize();
+    c.hold(&handle!("In"));
+    c.step();
+    let schedule: Vec<Vec<Handle>> = vec![vec![]; 2 * PHASES as usize];
+    assert_compiled_matches::<clock_model::State>("clock_model", c, &[handle!("out"), handle!("S", 23)], &schedule);
+}
@@ -0,0 +1,124 @@
+// Generated by z3mu from a finalized circuit. Do not edit.
+
+pub const NODES: usize = 7;
+pub const SWITCHES: usize = 2;
+pub const TICKS_PER_STEP: u32 = 1;
+pub const PHASE_LINES: [&[usize]; 5] = [&[], &[2], &[2], &[], &[]];
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Handle {
+    pub name: String,
+    pub index: Option<i8>,
+    pub sup: Option<u8>,
+}
+
+impl Handle {
+    pub fn new<T: Into<String>>(name: T, index: Option<i8>, sup: Option<u8>) -> Self {
+        Handle { name: name.into(), index, sup }
+    }
+}
+
+impl std::fmt::Display for Handle {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.name)?;
+        if let Some(index) = self.index {
+            write!(f, "_{}", index)?;
+        }
+        if let Some(sup) = self.sup {
+            write!(f, "^{}", sup)?;
+        }
+        Ok(())
+    }
+}
+
+pub struct State {
+    pub sources: [bool; NODES],
+    pub held: [bool; NODES],
+    pub powered: [bool; NODES],
+    pub positions: [bool; SWITCHES],
+    pub cycle: u64,
+    pub phase: u8,
+}
+
+impl Default for State {
+    fn default() -> Self {
+        State {
+            sources: [false; NODES],
+            held: [false, true, false, false, false, false, false],
+            powered: [true, true, false, false, true, false, false],
+            positions: [true, false],
+            cycle: 0,
+            phase: 1,
+        }
+    }
+}
+
+impl State {
+    fn node(handle: &Handle, action: &str) -> usize {
+        match (handle.name.as_str(), handle.index, handle.sup) {
+            ("Aa", Some(0), None) => 1,
+            ("Ab", Some(0), None) => 2,
+            ("G", None, None) => 0,
+            ("In", None, None) => 1,
+            ("S", Some(23), None) => 2,
+            ("out", None, None) => 5,
+            _ => panic!("Could not find node \"{}\" to {}", handle, action),
+        }
+    }
+
+    pub fn set(&mut self, handle: &Handle) {
+        self.sources[State::node(handle, "set")] = true;
+    }
+
+    pub fn hold(&mut self, handle: &Handle) {
+        self.held[State::node(handle, "hold")] = true;
+    }
+
+    pub fn release(&mut self, handle: &Handle) {
+        self.held[State::node(handle, "release")] = false;
+    }
+
+    pub fn inspect(&self, handle: &Handle) -> bool {
+        self.powered[State::node(handle, "inspect")]
+    }
+
+    pub fn step(&mut self) {
+        if self.phase == 5 {
+            self.phase = 1;
+            self.cycle += 1;
+        } else {
+            self.phase += 1;
+        }
+        for &line in PHASE_LINES[self.phase as usize - 1] {
+            self.sources[line] = true;
+        }
+        for _ in 0..TICKS_PER_STEP {
+            self.tick();
+        }
+        self.sources = [false; NODES];
+    }
+
+    #[allow(unused_mut, unused_variables, clippy::all)]
+    pub fn tick(&mut self) {
+        let s = &self.positions;
+        let mut p = self.sources;
+        for (p, held) in p.iter_mut().zip(&self.held) {
+            *p |= *held;
+        }
+        p[0] = true;
+        p[3] |= !s[1] && p[6];
+        p[3] |= s[1] && p[5];
+        p[0] |= !s[0] && p[4];
+        p[0] |= s[0] && p[3];
+        p[3] |= s[0] && p[0];
+        p[4] |= !s[0] && p[0];
+        p[5] |= s[1] && p[3];
+        p[6] |= !s[1] && p[3];
+        let e: [bool; SWITCHES] = [
+            p[1],
+            p[2],
+        ];
+        self.positions = e;
+        self.powered = p;
+    }
+}
@@ -0,0 +1,148 @@
+// Generated by z3mu from a finalized circuit. Do not edit.
+
+pub const NODES: usize = 12;
+pub const SWITCHES: usize = 5;
+pub const TICKS_PER_STEP: u32 = 2;
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Handle {
+    pub name: String,
+    pub index: Option<i8>,
+    pub sup: Option<u8>,
+}
+
+impl Handle {
+    pub fn new<T: Into<String>>(name: T, index: Option<i8>, sup: Option<u8>) -> Self {
+        Handle { name: name.into(), index, sup }
+    }
+}
+
+impl std::fmt::Display for Handle {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.name)?;
+        if let Some(index) = self.index {
+            write!(f, "_{}", index)?;
+        }
+        if let Some(sup) = self.sup {
+            write!(f, "^{}", sup)?;
+        }
+        Ok(())
+    }
+}
+
+pub struct State {
+    pub sources: [bool; NODES],
+    pub held: [bool; NODES],
+    pub powered: [bool; NODES],
+    pub positions: [bool; SWITCHES],
+    held_for: [u32; SWITCHES],
+}
+
+impl Default for State {
+    fn default() -> Self {
+        State {
+            sources: [false; NODES],
+            held: [false, false, false, false, false, false, false, false, false, false, false, false],
+            powered: [false, false, false, false, false, false, false, false, false, false, false, false],
+            positions: [false, false, false, false, false],
+            held_for: [0, 0, 0, 0, 0],
+        }
+    }
+}
+
+impl State {
+    fn node(handle: &Handle, action: &str) -> usize {
+        match (handle.name.as_str(), handle.index, handle.sup) {
+            ("Aa", Some(0), None) => 1,
+            ("Aa", Some(1), None) => 2,
+            ("Aa", Some(2), None) => 4,
+            ("Aa", Some(3), None) => 6,
+            ("Bz", None, None) => 11,
+            ("G", None, None) => 0,
+            ("In", None, None) => 1,
+            ("out", Some(0), None) => 2,
+            ("out", Some(1), None) => 4,
+            ("out", Some(2), None) => 6,
+            ("out", Some(3), None) => 8,
+            _ => panic!("Could not find node \"{}\" to {}", handle, action),
+        }
+    }
+
+    pub fn set(&mut self, handle: &Handle) {
+        self.sources[State::node(handle, "set")] = true;
+    }
+
+    pub fn hold(&mut self, handle: &Handle) {
+        self.held[State::node(handle, "hold")] = true;
+    }
+
+    pub fn release(&mut self, handle: &Handle) {
+        self.held[State::node(handle, "release")] = false;
+    }
+
+    pub fn inspect(&self, handle: &Handle) -> bool {
+        self.powered[State::node(handle, "inspect")]
+    }
+
+    pub fn step(&mut self) {
+        for _ in 0..TICKS_PER_STEP {
+            self.tick();
+        }
+        self.sources = [false; NODES];
+    }
+
+    #[allow(unused_mut, unused_variables, clippy::all)]
+    pub fn tick(&mut self) {
+        let s = &self.positions;
+        let mut p = self.sources;
+        for (p, held) in p.iter_mut().zip(&self.held) {
+            *p |= *held;
+        }
+        p[0] = true;
+        p[8] |= !s[4] && p[11];
+        p[8] |= s[4] && p[10];
+        p[0] |= !s[3] && p[9];
+        p[0] |= s[3] && p[8];
+        p[0] |= !s[2] && p[7];
+        p[0] |= s[2] && p[6];
+        p[0] |= !s[1] && p[5];
+        p[0] |= s[1] && p[4];
+        p[0] |= !s[0] && p[3];
+        p[0] |= s[0] && p[2];
+        p[2] |= s[0] && p[0];
+        p[3] |= !s[0] && p[0];
+        p[4] |= s[1] && p[0];
+        p[5] |= !s[1] && p[0];
+        p[6] |= s[2] && p[0];
+        p[7] |= !s[2] && p[0];
+        p[8] |= s[3] && p[0];
+        p[9] |= !s[3] && p[0];
+        p[10] |= s[4] && p[8];
+        p[11] |= !s[4] && p[8];
+        let e: [bool; SWITCHES] = [
+            p[1],
+            p[2],
+            p[4],
+            p[6],
+            p[11],
+        ];
+        self.advance(0, e[0], 1, 2);
+        self.advance(1, e[1], 2, 2);
+        self.advance(2, e[2], 3, 2);
+        self.advance(3, e[3], 4, 2);
+        self.advance(4, e[4], 1, 1);
+        self.powered = p;
+    }
+
+    fn advance(&mut self, switch: usize, energized: bool, pickup: u32, dropout: u32) {
+        if energized == self.positions[switch] {
+            self.held_for[switch] = 0;
+            return;
+        }
+        self.held_for[switch] += 1;
+        if self.held_for[switch] >= if energized { pickup } else { dropout } {
+            self.held_for[switch] = 0;
+            self.positions[switch] = energized;
+        }
+    }
+}
@@ -0,0 +1,193 @@
+// Generated by z3mu from a finalized circuit. Do not edit.
+
+pub const NODES: usize = 43;
+pub const SWITCHES: usize = 16;
+pub const TICKS_PER_STEP: u32 = 1;
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Handle {
+    pub name: String,
+    pub index: Option<i8>,
+    pub sup: Option<u8>,
+}
+
+impl Handle {
+    pub fn new<T: Into<String>>(name: T, index: Option<i8>, sup: Option<u8>) -> Self {
+        Handle { name: name.into(), index, sup }
+    }
+}
+
+impl std::fmt::Display for Handle {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.name)?;
+        if let Some(index) = self.index {
+            write!(f, "_{}", index)?;
+        }
+        if let Some(sup) = self.sup {
+            write!(f, "^{}", sup)?;
+        }
+        Ok(())
+    }
+}
+
+pub struct State {
+    pub sources: [bool; NODES],
+    pub held: [bool; NODES],
+    pub powered: [bool; NODES],
+    pub positions: [bool; SWITCHES],
+}
+
+impl Default for State {
+    fn default() -> Self {
+        State {
+            sources: [false; NODES],
+            held: [false, false, false, false, false, false, false, false, false, false, false, false, false, false, false, false, false, false, false, false, false, false, false, false, false, false, false, false, false, false, false, false, false, false, false, false, false, false, false, false, false, false, false],
+            powered: [false, false, false, false, false, false, false, false, false, false, false, false, false, false, false, false, false, false, false, false, false, false, false, false, false, false, false, false, false, false, false, false, false, false, false, false, false, false, false, false, false, false, false],
+            positions: [false, false, false, false, false, false, false, false, false, false, false, false, false, false, false, false],
+        }
+    }
+}
+
+impl State {
+    fn node(handle: &Handle, action: &str) -> usize {
+        match (handle.name.as_str(), handle.index, handle.sup) {
+            ("Aa", Some(0), None) => 5,
+            ("Aa", Some(1), None) => 9,
+            ("Aa", Some(2), None) => 13,
+            ("Aa", Some(3), None) => 17,
+            ("Aa", Some(4), None) => 21,
+            ("Aa", Some(5), None) => 25,
+            ("Aa", Some(6), None) => 29,
+            ("Aa", Some(7), None) => 33,
+            ("Ab", Some(0), None) => 35,
+            ("Ab", Some(1), None) => 36,
+            ("Ab", Some(2), None) => 37,
+            ("Ab", Some(3), None) => 38,
+            ("Ab", Some(4), None) => 39,
+            ("Ab", Some(5), None) => 40,
+            ("Ab", Some(6), None) => 41,
+            ("Ab", Some(7), None) => 42,
+            ("G", None, None) => 0,
+            ("Ga", None, None) => 1,
+            ("S", Some(5), None) => 2,
+            _ => panic!("Could not find node \"{}\" to {}", handle, action),
+        }
+    }
+
+    pub fn set(&mut self, handle: &Handle) {
+        self.sources[State::node(handle, "set")] = true;
+    }
+
+    pub fn hold(&mut self, handle: &Handle) {
+        self.held[State::node(handle, "hold")] = true;
+    }
+
+    pub fn release(&mut self, handle: &Handle) {
+        self.held[State::node(handle, "release")] = false;
+    }
+
+    pub fn inspect(&self, handle: &Handle) -> bool {
+        self.powered[State::node(handle, "inspect")]
+    }
+
+    pub fn step(&mut self) {
+        for _ in 0..TICKS_PER_STEP {
+            self.tick();
+        }
+        self.sources = [false; NODES];
+    }
+
+    #[allow(unused_mut, unused_variables, clippy::all)]
+    pub fn tick(&mut self) {
+        let s = &self.positions;
+        let mut p = self.sources;
+        for (p, held) in p.iter_mut().zip(&self.held) {
+            *p |= *held;
+        }
+        p[0] = true;
+        p[31] |= !s[15] && p[34];
+        p[31] |= s[15] && p[33];
+        p[27] |= !s[13] && p[30];
+        p[27] |= s[13] && p[29];
+        p[23] |= !s[11] && p[26];
+        p[23] |= s[11] && p[25];
+        p[19] |= !s[9] && p[22];
+        p[19] |= s[9] && p[21];
+        p[15] |= !s[7] && p[18];
+        p[15] |= s[7] && p[17];
+        p[11] |= !s[5] && p[14];
+        p[11] |= s[5] && p[13];
+        p[7] |= !s[3] && p[10];
+        p[7] |= s[3] && p[9];
+        p[3] |= !s[1] && p[6];
+        p[3] |= s[1] && p[5];
+        p[2] |= !s[14] && p[32];
+        p[2] |= s[14] && p[31];
+        p[2] |= !s[12] && p[28];
+        p[2] |= s[12] && p[27];
+        p[2] |= !s[10] && p[24];
+        p[2] |= s[10] && p[23];
+        p[2] |= !s[8] && p[20];
+        p[2] |= s[8] && p[19];
+        p[2] |= !s[6] && p[16];
+        p[2] |= s[6] && p[15];
+        p[2] |= !s[4] && p[12];
+        p[2] |= s[4] && p[11];
+        p[2] |= !s[2] && p[8];
+        p[2] |= s[2] && p[7];
+        p[2] |= !s[0] && p[4];
+        p[2] |= s[0] && p[3];
+        p[3] |= s[0] && p[2];
+        p[4] |= !s[0] && p[2];
+        p[7] |= s[2] && p[2];
+        p[8] |= !s[2] && p[2];
+        p[11] |= s[4] && p[2];
+        p[12] |= !s[4] && p[2];
+        p[15] |= s[6] && p[2];
+        p[16] |= !s[6] && p[2];
+        p[19] |= s[8] && p[2];
+        p[20] |= !s[8] && p[2];
+        p[23] |= s[10] && p[2];
+        p[24] |= !s[10] && p[2];
+        p[27] |= s[12] && p[2];
+        p[28] |= !s[12] && p[2];
+        p[31] |= s[14] && p[2];
+        p[32] |= !s[14] && p[2];
+        p[5] |= s[1] && p[3];
+        p[6] |= !s[1] && p[3];
+        p[9] |= s[3] && p[7];
+        p[10] |= !s[3] && p[7];
+        p[13] |= s[5] && p[11];
+        p[14] |= !s[5] && p[11];
+        p[17] |= s[7] && p[15];
+        p[18] |= !s[7] && p[15];
+        p[21] |= s[9] && p[19];
+        p[22] |= !s[9] && p[19];
+        p[25] |= s[11] && p[23];
+        p[26] |= !s[11] && p[23];
+        p[29] |= s[13] && p[27];
+        p[30] |= !s[13] && p[27];
+        p[33] |= s[15] && p[31];
+        p[34] |= !s[15] && p[31];
+        let e: [bool; SWITCHES] = [
+            p[35],
+            p[1],
+            p[36],
+            p[1],
+            p[37],
+            p[1],
+            p[38],
+            p[1],
+            p[39],
+            p[1],
+            p[40],
+            p[1],
+            p[41],
+            p[1],
+            p[42],
+            p[1],
+        ];
+        self.positions = e;
+        self.powered = p;
+    }
+}
@@ -0,0 +1,119 @@
+// Generated by z3mu from a finalized circuit. Do not edit.
+
+pub const NODES: usize = 9;
+pub const SWITCHES: usize = 3;
+pub const TICKS_PER_STEP: u32 = 1;
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Handle {
+    pub name: String,
+    pub index: Option<i8>,
+    pub sup: Option<u8>,
+}
+
+impl Handle {
+    pub fn new<T: Into<String>>(name: T, index: Option<i8>, sup: Option<u8>) -> Self {
+        Handle { name: name.into(), index, sup }
+    }
+}
+
+impl std::fmt::Display for Handle {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.name)?;
+        if let Some(index) = self.index {
+            write!(f, "_{}", index)?;
+        }
+        if let Some(sup) = self.sup {
+            write!(f, "^{}", sup)?;
+        }
+        Ok(())
+    }
+}
+
+pub struct State {
+    pub sources: [bool; NODES],
+    pub held: [bool; NODES],
+    pub powered: [bool; NODES],
+    pub positions: [bool; SWITCHES],
+}
+
+impl Default for State {
+    fn default() -> Self {
+        State {
+            sources: [false; NODES],
+            held: [false, false, false, false, false, false, false, false, false],
+            powered: [false, false, false, false, false, false, false, false, false],
+            positions: [false, false, false],
+        }
+    }
+}
+
+impl State {
+    fn node(handle: &Handle, action: &str) -> usize {
+        match (handle.name.as_str(), handle.index, handle.sup) {
+            ("Aa", None, None) => 3,
+            ("Ab", None, None) => 4,
+            ("Ac", None, None) => 5,
+            ("G", None, None) => 0,
+            ("Ia", None, None) => 3,
+            ("Ib", None, None) => 4,
+            ("Ic", None, None) => 5,
+            ("X", None, None) => 1,
+            ("Y", None, None) => 2,
+            _ => panic!("Could not find node \"{}\" to {}", handle, action),
+        }
+    }
+
+    pub fn set(&mut self, handle: &Handle) {
+        self.sources[State::node(handle, "set")] = true;
+    }
+
+    pub fn hold(&mut self, handle: &Handle) {
+        self.held[State::node(handle, "hold")] = true;
+    }
+
+    pub fn release(&mut self, handle: &Handle) {
+        self.held[State::node(handle, "release")] = false;
+    }
+
+    pub fn inspect(&self, handle: &Handle) -> bool {
+        self.powered[State::node(handle, "inspect")]
+    }
+
+    pub fn step(&mut self) {
+        for _ in 0..TICKS_PER_STEP {
+            self.tick();
+        }
+        self.sources = [false; NODES];
+    }
+
+    #[allow(unused_mut, unused_variables, clippy::all)]
+    pub fn tick(&mut self) {
+        let s = &self.positions;
+        let mut p = self.sources;
+        for (p, held) in p.iter_mut().zip(&self.held) {
+            *p |= *held;
+        }
+        p[0] = true;
+        // the contacts between nodes 0, 1, 2, 6, 7, 8 form a loop, so they are relaxed until nothing changes
+        loop {
+            let mut changed = false;
+            if s[0] && p[0] != p[1] { p[0] = true; p[1] = true; changed = true; }
+            if s[1] && p[0] != p[2] { p[0] = true; p[2] = true; changed = true; }
+            if !s[0] && p[0] != p[6] { p[0] = true; p[6] = true; changed = true; }
+            if !s[1] && p[0] != p[7] { p[0] = true; p[7] = true; changed = true; }
+            if s[2] && p[1] != p[2] { p[1] = true; p[2] = true; changed = true; }
+            if !s[2] && p[1] != p[8] { p[1] = true; p[8] = true; changed = true; }
+            if !changed {
+                break;
+            }
+        }
+        let e: [bool; SWITCHES] = [
+            p[3],
+            p[4],
+            p[5],
+        ];
+        self.positions = e;
+        self.powered = p;
+    }
+}